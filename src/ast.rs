@@ -0,0 +1,427 @@
+use crate::enums::Variant;
+use crate::errors::*;
+use crate::functions::Param;
+use crate::patterns::Pattern;
+use crate::reader::*;
+use crate::tokens::*;
+use crate::types::Type;
+use serde::Serialize;
+
+/// Expression of the token tree, with its position in the source
+#[derive(Debug, PartialEq)]
+pub struct Node {
+    kind: &'static str,
+    /// Name or literal of a leaf, like a variable or a number
+    value: Option<String>,
+    start: usize,
+    end: usize,
+    children: Vec<Node>,
+}
+
+/// Returns the name of the kind of expression
+fn kind(e: &Expr) -> &'static str {
+    match e {
+        Expr::Number(_) => "Number",
+        Expr::Bool(_) => "Bool",
+        Expr::Var(_) => "Var",
+        Expr::Symbol(_) => "Symbol",
+        Expr::Str(_) => "Str",
+        Expr::EnumElement(_) => "EnumElement",
+        Expr::EnumValue { .. } => "EnumValue",
+        Expr::Add(..) => "Add",
+        Expr::Sub(..) => "Sub",
+        Expr::Mul(..) => "Mul",
+        Expr::Div(..) => "Div",
+        Expr::Mod(..) => "Mod",
+        Expr::Equal(..) => "Equal",
+        Expr::GreaterThan(..) => "GreaterThan",
+        Expr::GreaterEqualThan(..) => "GreaterEqualThan",
+        Expr::LessThan(..) => "LessThan",
+        Expr::LessEqualThan(..) => "LessEqualThan",
+        Expr::NEqual(..) => "NEqual",
+        Expr::And(..) => "And",
+        Expr::Or(..) => "Or",
+        Expr::Not(..) => "Not",
+        Expr::If(..) => "If",
+        Expr::Let(..) => "Let",
+        Expr::Set(..) => "Set",
+        Expr::Sequence(..) => "Sequence",
+        Expr::For(..) => "For",
+        Expr::While(..) => "While",
+        Expr::Define(..) => "Define",
+        Expr::Call(..) => "Call",
+        Expr::Print(..) => "Print",
+        Expr::Enum(..) => "Enum",
+        Expr::EnumConstruct(..) => "EnumConstruct",
+        Expr::Variants(..) => "Variants",
+        Expr::Match(..) => "Match",
+        Expr::List(_) => "List",
+        Expr::Input => "Input",
+        Expr::Args => "Args",
+        Expr::Env(..) => "Env",
+        Expr::Exit(..) => "Exit",
+        Expr::Empty => "Empty",
+    }
+}
+
+/// Returns the name or the literal of a leaf, or the item built by an enum constructor
+fn value(e: &Expr) -> Option<String> {
+    match e {
+        Expr::Number(x) => Some(x.to_string()),
+        Expr::Bool(x) => Some(x.to_string()),
+        Expr::Var(x) | Expr::Symbol(x) | Expr::Str(x) | Expr::EnumElement(x) => Some(x.to_string()),
+        Expr::EnumConstruct(x, ..) => Some(x.to_string()),
+        Expr::EnumValue {
+            enum_name, variant, ..
+        } => Some(format!("{}.{}", enum_name, variant)),
+        _ => None,
+    }
+}
+
+/// Returns the type as it is written in an annotation
+fn type_name(t: &Type) -> String {
+    match t {
+        Type::Number => "int".to_string(),
+        Type::Bool => "bool".to_string(),
+        Type::Str => "str".to_string(),
+        Type::List => "list".to_string(),
+        Type::Symbol => "symbol".to_string(),
+        Type::Enum(name) => name.to_string(),
+        t => format!("{:?}", t),
+    }
+}
+
+impl Node {
+    /// Returns the node of the expression, spanning `span` unless it knows its own position
+    /// Its parameters, types, patterns and enum items are nodes spanning the expression
+    fn new(e: &Expr, span: Span) -> Self {
+        let source = e.get_source();
+        let span = source.and_then(Source::get_span).unwrap_or(span);
+        let mut children: Vec<Node> = e
+            .children()
+            .into_iter()
+            .enumerate()
+            .map(|(i, child)| {
+                // Names and literals are found by the expression containing them
+                let child_span = source.and_then(|s| s.get_child_span(i));
+                Node::new(child, child_span.unwrap_or(span))
+            })
+            .collect();
+        match e {
+            Expr::EnumValue { fields, .. } => {
+                children.extend(fields.iter().map(|x| Node::new(x, span)));
+            }
+            Expr::Let(_, Some(t), _, _) => children.insert(1, Node::type_node(t, span)),
+            Expr::Define(_, params, ret, _, _) => {
+                let signature = params
+                    .iter()
+                    .map(|p| Node::param(p, span))
+                    .chain(ret.iter().map(|t| Node::type_node(t, span)));
+                children.splice(1..1, signature.collect::<Vec<Node>>());
+            }
+            Expr::Enum(_, variants, _) => {
+                children.extend(variants.iter().map(|v| Node::variant(v, span)));
+            }
+            Expr::Match(_, arms, _) => {
+                // Each arm is its pattern followed by its body
+                let bodies = children.split_off(1);
+                children.extend(arms.iter().zip(bodies).map(|((pattern, _), body)| {
+                    Node::leaf("Arm", None, span, vec![Node::pattern(pattern, span), body])
+                }));
+            }
+            _ => {}
+        }
+        Node::leaf(kind(e), value(e), span, children)
+    }
+
+    fn leaf(kind: &'static str, value: Option<String>, span: Span, children: Vec<Node>) -> Self {
+        Node {
+            kind,
+            value,
+            start: span.start,
+            end: span.end,
+            children,
+        }
+    }
+
+    fn type_node(t: &Type, span: Span) -> Self {
+        Node::leaf("Type", Some(type_name(t)), span, vec![])
+    }
+
+    /// Returns the node of a parameter, with its type and its default value
+    fn param(p: &Param, span: Span) -> Self {
+        let kind = if p.is_rest() { "RestParam" } else { "Param" };
+        let mut children: Vec<Node> = p
+            .get_annotation()
+            .iter()
+            .map(|t| Node::type_node(t, span))
+            .collect();
+        children.extend(p.get_default().iter().map(|x| Node::new(x, span)));
+        Node::leaf(kind, Some(p.get_name()), span, children)
+    }
+
+    /// Returns the node of an enum item, with its fields
+    fn variant(v: &Variant, span: Span) -> Self {
+        let fields = v
+            .get_fields()
+            .into_iter()
+            .map(|f| Node::leaf("Field", Some(f), span, vec![]))
+            .collect();
+        Node::leaf("Variant", Some(v.get_name()), span, fields)
+    }
+
+    fn pattern(p: &Pattern, span: Span) -> Self {
+        match p {
+            Pattern::Wildcard => Node::leaf("WildcardPattern", None, span, vec![]),
+            Pattern::Literal(x) => {
+                Node::leaf("LiteralPattern", None, span, vec![Node::new(x, span)])
+            }
+            Pattern::Binding(name) => {
+                Node::leaf("BindingPattern", Some(name.to_string()), span, vec![])
+            }
+            Pattern::List(items, rest) => {
+                let mut children: Vec<Node> =
+                    items.iter().map(|x| Node::pattern(x, span)).collect();
+                children.extend(
+                    rest.iter().map(|name| {
+                        Node::leaf("RestPattern", Some(name.to_string()), span, vec![])
+                    }),
+                );
+                Node::leaf("ListPattern", None, span, children)
+            }
+            Pattern::Variant(name, fields) => Node::leaf(
+                "VariantPattern",
+                Some(name.to_string()),
+                span,
+                fields.iter().map(|x| Node::pattern(x, span)).collect(),
+            ),
+        }
+    }
+
+    /// Returns the node of the program, as read by `Expr::token_tree`
+    pub fn token_tree(code: &str) -> Result<Self, LangError> {
+        Ok(Node::new(&Expr::token_tree(code)?, Span::default()))
+    }
+
+    /// Returns the node as an S-expression, like `(Add (Var x) (Number 1))`
+    pub fn to_sexp(&self) -> String {
+        let mut parts = vec![self.kind.to_string()];
+        match (self.kind, &self.value) {
+            ("Str", Some(x)) => parts.push(format!("{:?}", x)),
+            (_, Some(x)) => parts.push(x.to_string()),
+            (_, None) => {}
+        }
+        parts.extend(self.children.iter().map(|c| c.to_sexp()));
+        format!("({})", parts.join(" "))
+    }
+
+    /// Returns the node as indented JSON, the positions being computed from the code
+    pub fn to_json(&self, code: &str) -> String {
+        let mut out = serde_json::to_string_pretty(&self.to_json_node(code)).unwrap_or_default();
+        out.push('\n');
+        out
+    }
+
+    fn to_json_node(&self, code: &str) -> JsonNode {
+        let position = |index: usize| {
+            let (line, column) = line_column(code, index);
+            JsonPosition { line, column }
+        };
+        JsonNode {
+            kind: self.kind,
+            value: self.value.clone(),
+            span: JsonSpan {
+                start: position(self.start),
+                end: position(self.end),
+            },
+            children: self.children.iter().map(|c| c.to_json_node(code)).collect(),
+        }
+    }
+}
+
+/// Node written as JSON, with the lines and the columns of its position
+#[derive(Serialize)]
+struct JsonNode {
+    kind: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    value: Option<String>,
+    span: JsonSpan,
+    children: Vec<JsonNode>,
+}
+
+#[derive(Serialize)]
+struct JsonSpan {
+    start: JsonPosition,
+    end: JsonPosition,
+}
+
+#[derive(Serialize)]
+struct JsonPosition {
+    line: usize,
+    column: usize,
+}
+
+/// Code of a program, giving the positions of the expressions of its token tree
+pub(crate) struct SourceMap {
+    code: String,
+    /// Expressions being evaluated, the innermost last
+    evaluated: Vec<Evaluated>,
+}
+
+/// Expression being evaluated, with the names and literals it contains
+struct Evaluated {
+    span: Option<Span>,
+    /// Sub-expressions without a position of their own, with the position they were read at
+    leaves: Vec<(Expr, Span)>,
+    /// Index of the leaf following the last one evaluated
+    next: usize,
+}
+
+impl Evaluated {
+    /// Returns the position of the leaf, searched from the one following the last evaluated,
+    /// as the sub-expressions are evaluated in order, again for the core of a loop
+    fn find(&mut self, e: &Expr) -> Option<Span> {
+        let n = self.leaves.len();
+        let i = (0..n)
+            .map(|k| (self.next + k) % n)
+            .find(|i| self.leaves[*i].0 == *e)?;
+        self.next = i + 1;
+        Some(self.leaves[i].1)
+    }
+}
+
+impl SourceMap {
+    pub(crate) fn new(code: &str) -> Self {
+        SourceMap {
+            code: code.to_string(),
+            evaluated: Vec::new(),
+        }
+    }
+
+    /// Returns the position of the expression whose evaluation starts, ended by `leave`
+    /// Names and literals take the position they were read at in the expression containing
+    /// them, and the expressions without a position, like default values, the one of it
+    pub(crate) fn enter(&mut self, e: &Expr) -> Option<Span> {
+        let position = match (e.get_span(), self.evaluated.last_mut()) {
+            (Some(span), _) => Some(span),
+            (None, Some(parent)) => parent.find(e).or(parent.span),
+            (None, None) => None,
+        };
+        let leaves = match e.get_source() {
+            Some(source) => e
+                .children()
+                .into_iter()
+                .enumerate()
+                .filter(|(_, child)| child.get_span().is_none())
+                .filter_map(|(i, child)| source.get_child_span(i).map(|s| (child.clone(), s)))
+                .collect(),
+            None => Vec::new(),
+        };
+        self.evaluated.push(Evaluated {
+            span: position,
+            leaves,
+            next: 0,
+        });
+        position
+    }
+
+    /// Ends the evaluation of the innermost expression
+    pub(crate) fn leave(&mut self) {
+        self.evaluated.pop();
+    }
+
+    /// Returns the line and the column of the start of the position
+    pub(crate) fn position(&self, span: Span) -> (usize, usize) {
+        line_column(&self.code, span.start)
+    }
+
+    /// Returns the line of the start of the position
+    pub(crate) fn line(&self, span: Span) -> usize {
+        self.position(span).0
+    }
+
+    /// Returns the source of the expression on one line, shortened if it is long
+    /// Without its position, it is written from its name or its literal
+    pub(crate) fn snippet(&self, e: &Expr, span: Option<Span>) -> String {
+        match (span, value(e)) {
+            (Some(span), _) => shorten(
+                &self.code[span.start..span.end]
+                    .split_whitespace()
+                    .collect::<Vec<&str>>()
+                    .join(" "),
+            ),
+            (None, Some(x)) if matches!(e, Expr::Str(_)) => shorten(&format!("\"{}\"", x)),
+            (None, Some(x)) if matches!(e, Expr::Symbol(_)) => format!("'{}", x),
+            (None, Some(x)) => shorten(&x),
+            (None, None) => "<expression>".to_string(),
+        }
+    }
+}
+
+/// Returns the text, cut to 60 characters
+pub(crate) fn shorten(text: &str) -> String {
+    if text.chars().count() > 60 {
+        format!("{}...", text.chars().take(57).collect::<String>())
+    } else {
+        text.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests_ast {
+    use crate::ast::*;
+
+    #[test]
+    fn test_to_sexp() {
+        let node = Node::token_tree("(let x (+ 1 \"a\"))\n(print x)").unwrap();
+        assert_eq!(
+            node.to_sexp(),
+            "(Sequence (Let (Var x) (Add (Number 1) (Str \"a\"))) (Print (Var x)))"
+        );
+        assert!(Node::token_tree("(let x").is_err());
+    }
+
+    #[test]
+    fn test_to_sexp_all_fields() {
+        let node = Node::token_tree(
+            "((let x : int 1) (def f (a : int 1) & r -> int a) (enum e a (b x)) \
+             (match x ((list y & z) y) (1 0) ((e.b _) 2)))",
+        )
+        .unwrap();
+        assert_eq!(
+            node.to_sexp(),
+            "(Sequence (Let (Var x) (Type int) (Number 1)) \
+             (Define (Var f) (Param a (Type int) (Number 1)) (RestParam r) (Type int) (Var a)) \
+             (Enum (Var e) (Variant a) (Variant b (Field x))) \
+             (Match (Var x) (Arm (ListPattern (BindingPattern y) (RestPattern z)) (Var y)) \
+             (Arm (LiteralPattern (Number 1)) (Number 0)) \
+             (Arm (VariantPattern e.b (WildcardPattern)) (Number 2))))"
+        );
+    }
+
+    #[test]
+    fn test_spans() {
+        let code = "(match x\n  (1 (+ y 1))\n  (_ x))";
+        let node = Node::token_tree(code).unwrap();
+        let bodies: Vec<&Node> = std::iter::once(&node.children[0])
+            .chain(node.children[1..].iter().map(|arm| &arm.children[1]))
+            .collect();
+        let spans: Vec<&str> = bodies.iter().map(|c| &code[c.start..c.end]).collect();
+        assert_eq!(spans, vec!["x", "(+ y 1)", "x"]);
+        assert_eq!(bodies[2].start, code.len() - 3);
+    }
+
+    #[test]
+    fn test_to_json() {
+        let code = "(print \"a\tb\")";
+        let json: serde_json::Value =
+            serde_json::from_str(&Node::token_tree(code).unwrap().to_json(code)).unwrap();
+        assert_eq!(json["kind"], "Print");
+        assert_eq!(json["children"][0]["value"], "a\tb");
+        assert_eq!(
+            json["span"]["end"],
+            serde_json::json!({"line": 1, "column": 14})
+        );
+        assert!(json.get("value").is_none());
+    }
+}
@@ -0,0 +1,15 @@
+use std::io;
+use std::process;
+
+/// Language server for Rusil, speaking JSON-RPC on the standard input and output
+fn main() {
+    let stdin = io::stdin();
+    let code = match rusil::lsp::serve(&mut stdin.lock(), &mut io::stdout()) {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("{}", e);
+            e.exit_code()
+        }
+    };
+    process::exit(code);
+}
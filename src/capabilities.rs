@@ -0,0 +1,46 @@
+use std::fmt;
+
+/// Access to the outside world, that an interpreter can deny to the programs it evaluates
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum Capability {
+    /// Reading the input, with `(input)`
+    Stdin,
+    /// Writing the output, with `(print ...)`
+    Stdout,
+    /// Reading and writing files
+    Filesystem,
+    /// Reading the environment variables, with `(env NAME)`
+    Env,
+    /// Reading the time
+    Clock,
+    /// Generating random numbers
+    Random,
+}
+
+impl Capability {
+    /// Returns every capability
+    pub fn all() -> Vec<Self> {
+        vec![
+            Capability::Stdin,
+            Capability::Stdout,
+            Capability::Filesystem,
+            Capability::Env,
+            Capability::Clock,
+            Capability::Random,
+        ]
+    }
+}
+
+impl fmt::Display for Capability {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Capability::Stdin => "stdin",
+            Capability::Stdout => "stdout",
+            Capability::Filesystem => "filesystem",
+            Capability::Env => "env",
+            Capability::Clock => "clock",
+            Capability::Random => "random",
+        };
+        write!(f, "{}", name)
+    }
+}
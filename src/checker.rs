@@ -0,0 +1,678 @@
+use crate::enums::*;
+use crate::errors::*;
+use crate::functions::arity;
+use crate::patterns::*;
+use crate::tokens::*;
+use crate::types::*;
+use std::collections::{HashMap, HashSet};
+
+/// Type of an expression during the inference
+/// `Unknown(i)` is a type not inferred yet
+#[derive(Debug, PartialEq, Clone)]
+enum Ty {
+    Known(Type),
+    Unknown(usize),
+}
+
+/// Type of a function: the unknown types in `generics` are instantiated
+/// again at each call, so the function can be called with different types
+/// `args` are the types of the parameters, except the rest parameter
+#[derive(Debug, Clone)]
+struct Signature {
+    generics: Vec<usize>,
+    args: Vec<Ty>,
+    arity: (usize, Option<usize>),
+    ret: Ty,
+}
+
+/// Static type checker
+/// Walks the token tree in evaluation order and infers the type of every
+/// `let` binding and of the arguments and result of every `def`
+pub struct Checker {
+    variables: HashMap<String, Ty>,
+    functions: HashMap<String, Signature>,
+    enums: HashMap<String, Vec<Variant>>,
+    substitution: Vec<Option<Ty>>,
+    errors: Vec<LangError>,
+    signatures: Vec<(String, Signature)>,
+    /// Number of function cores being checked: as names are scoped dynamically, the variables
+    /// and the functions they use without defining them can be defined before they are called
+    functions_checked: usize,
+}
+
+impl Default for Checker {
+    fn default() -> Self {
+        Checker::new()
+    }
+}
+
+impl Checker {
+    pub fn new() -> Self {
+        Checker {
+            variables: HashMap::new(),
+            functions: HashMap::new(),
+            enums: builtin_enums(),
+            substitution: Vec::new(),
+            errors: Vec::new(),
+            signatures: Vec::new(),
+            functions_checked: 0,
+        }
+    }
+
+    /// Checks the program and returns all the errors found
+    pub fn check(&mut self, tree: &Expr) -> Vec<LangError> {
+        self.infer(tree, "");
+        std::mem::take(&mut self.errors)
+    }
+
+    /// Returns the inferred signature of every function defined, in definition order
+    pub fn signatures(&self) -> Vec<String> {
+        self.signatures
+            .iter()
+            .map(|(name, signature)| {
+                let mut args: Vec<String> =
+                    signature.args.iter().map(|t| self.display(t)).collect();
+                if signature.arity.1.is_none() {
+                    args.push(format!("& {:?}", Type::List));
+                }
+                format!(
+                    "{}: ({}) -> {}",
+                    name,
+                    args.join(", "),
+                    self.display(&signature.ret)
+                )
+            })
+            .collect()
+    }
+
+    fn display(&self, t: &Ty) -> String {
+        match self.resolve(t) {
+            Ty::Known(t) => format!("{:?}", t),
+            Ty::Unknown(i) => format!("'t{}", i),
+        }
+    }
+
+    fn fresh(&mut self) -> Ty {
+        self.substitution.push(None);
+        Ty::Unknown(self.substitution.len() - 1)
+    }
+
+    /// Follows the substitution until a known type or a free unknown type
+    fn resolve(&self, t: &Ty) -> Ty {
+        match t {
+            Ty::Unknown(i) => match &self.substitution[*i] {
+                Some(u) => self.resolve(u),
+                None => t.clone(),
+            },
+            _ => t.clone(),
+        }
+    }
+
+    /// Makes the two types equal, or reports a TypeError
+    fn expect(&mut self, found: &Ty, expected: &Ty, expr_str: &str) {
+        match (self.resolve(found), self.resolve(expected)) {
+            (Ty::Unknown(i), Ty::Unknown(j)) if i == j => {}
+            (Ty::Unknown(i), t) | (t, Ty::Unknown(i)) => self.substitution[i] = Some(t),
+            (Ty::Known(f), Ty::Known(e)) => {
+                if f != e {
+                    self.errors
+                        .push(LangError::new_type_error(e, f, expr_str.to_string()));
+                }
+            }
+        }
+    }
+
+    /// Returns the type of the annotation, or a new unknown type if there is none
+    /// An annotation naming an unknown type is reported, and then ignored
+    fn annotated(&mut self, annotation: &Option<Type>, expr_str: &str) -> Ty {
+        match annotation
+            .as_ref()
+            .map(|t| (t, t.check_known(&self.enums, expr_str)))
+        {
+            Some((t, Ok(()))) => Ty::Known(t.clone()),
+            Some((_, Err(err))) => {
+                self.errors.push(err);
+                self.fresh()
+            }
+            None => self.fresh(),
+        }
+    }
+
+    /// Infers the type of the sub expression and reports it if it is not the expected one
+    fn infer_expecting(&mut self, e: &Expr, expected: Type, expr_str: &str) {
+        let t = self.infer(e, expr_str);
+        self.expect(&t, &Ty::Known(expected), expr_str);
+    }
+
+    /// Returns the name held by the expression, or reports a TypeError
+    fn name(&mut self, e: &Expr, expr_str: &str) -> Option<String> {
+        match e.get_var(expr_str.to_string()) {
+            Ok(name) => Some(name),
+            Err(err) => {
+                self.errors.push(err);
+                None
+            }
+        }
+    }
+
+    /// Binds the names to their types and returns the previous bindings
+    fn bind(&mut self, bindings: Vec<(String, Ty)>) -> Vec<(String, Option<Ty>)> {
+        bindings
+            .into_iter()
+            .map(|(name, t)| (name.clone(), self.variables.insert(name, t)))
+            .collect()
+    }
+
+    fn restore(&mut self, previous_values: Vec<(String, Option<Ty>)>) {
+        for (name, t) in previous_values.into_iter().rev() {
+            match t {
+                Some(t) => self.variables.insert(name, t),
+                None => self.variables.remove(&name),
+            };
+        }
+    }
+
+    /// Unknown types still free in the type of a variable
+    fn free_in_variables(&self) -> HashSet<usize> {
+        self.variables
+            .values()
+            .filter_map(|t| match self.resolve(t) {
+                Ty::Unknown(i) => Some(i),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn instantiate(&mut self, signature: &Signature) -> (Vec<Ty>, Ty) {
+        let mut fresh: HashMap<usize, Ty> = HashMap::new();
+        for i in signature.generics.iter() {
+            let t = self.fresh();
+            fresh.insert(*i, t);
+        }
+        let mut replace = |t: &Ty| match self.resolve(t) {
+            Ty::Unknown(i) => fresh.get(&i).cloned().unwrap_or(Ty::Unknown(i)),
+            t => t,
+        };
+        let args = signature.args.iter().map(&mut replace).collect();
+        (args, replace(&signature.ret))
+    }
+
+    /// Infers the type of the expression, positioning the errors reported in it that are
+    /// not in a sub-expression at it
+    fn infer(&mut self, e: &Expr, parent_str: &str) -> Ty {
+        let reported = self.errors.len();
+        let t = self.infer_node(e, parent_str);
+        let span = e.get_span();
+        let errors: Vec<LangError> = self
+            .errors
+            .drain(reported..)
+            .map(|err| err.with_span(span))
+            .collect();
+        self.errors.extend(errors);
+        t
+    }
+
+    fn infer_node(&mut self, e: &Expr, parent_str: &str) -> Ty {
+        match e {
+            Expr::Number(_) => Ty::Known(Type::Number),
+            Expr::Bool(_) => Ty::Known(Type::Bool),
+            Expr::Str(_) => Ty::Known(Type::Str),
+            Expr::Symbol(_) => Ty::Known(Type::Symbol),
+            Expr::Empty => Ty::Known(Type::Expression),
+            Expr::EnumValue { enum_name, .. } => Ty::Known(Type::Enum(enum_name.to_string())),
+            Expr::Var(x) => match self.variables.get(x) {
+                Some(t) => t.clone(),
+                None if self.functions_checked > 0 => self.fresh(),
+                None => {
+                    self.errors.push(LangError::new_undefined_variable_error(
+                        x.to_string(),
+                        parent_str.to_string(),
+                    ));
+                    self.fresh()
+                }
+            },
+            Expr::Add(x, y, s)
+            | Expr::Sub(x, y, s)
+            | Expr::Mul(x, y, s)
+            | Expr::Div(x, y, s)
+            | Expr::Mod(x, y, s) => {
+                self.infer_expecting(x, Type::Number, s);
+                self.infer_expecting(y, Type::Number, s);
+                Ty::Known(Type::Number)
+            }
+            Expr::GreaterThan(x, y, s)
+            | Expr::GreaterEqualThan(x, y, s)
+            | Expr::LessThan(x, y, s)
+            | Expr::LessEqualThan(x, y, s) => {
+                self.infer_expecting(x, Type::Number, s);
+                self.infer_expecting(y, Type::Number, s);
+                Ty::Known(Type::Bool)
+            }
+            Expr::Equal(x, y, s) | Expr::NEqual(x, y, s) => {
+                let tx = self.infer(x, s);
+                let ty = self.infer(y, s);
+                self.expect(&ty, &tx, s);
+                Ty::Known(Type::Bool)
+            }
+            Expr::And(x, y, s) | Expr::Or(x, y, s) => {
+                self.infer_expecting(x, Type::Bool, s);
+                self.infer_expecting(y, Type::Bool, s);
+                Ty::Known(Type::Bool)
+            }
+            Expr::Not(x, s) => {
+                self.infer_expecting(x, Type::Bool, s);
+                Ty::Known(Type::Bool)
+            }
+            Expr::If(b, x, y, s) => {
+                self.infer_expecting(b, Type::Bool, s);
+                let tx = self.infer(x, s);
+                let ty = self.infer(y, s);
+                self.expect(&ty, &tx, s);
+                tx
+            }
+            Expr::Let(name, annotation, x, s) => {
+                let mut t = self.infer(x, s);
+                if annotation.is_some() {
+                    let annotated = self.annotated(annotation, s);
+                    self.expect(&t, &annotated, s);
+                    t = annotated;
+                }
+                if let Some(var_name) = self.name(name, s) {
+                    self.variables.insert(var_name, t);
+                }
+                Ty::Known(Type::Expression)
+            }
+            Expr::Set(name, x, s) => {
+                let t = self.infer(x, s);
+                if let Some(var_name) = self.name(name, s) {
+                    match self.variables.get(&var_name).cloned() {
+                        Some(previous) => self.expect(&t, &previous, s),
+                        None if self.functions_checked > 0 => {}
+                        None => self.errors.push(LangError::new_undefined_variable_error(
+                            var_name,
+                            s.to_string(),
+                        )),
+                    }
+                }
+                Ty::Known(Type::Expression)
+            }
+            Expr::Sequence(v, s) => {
+                let mut result = Ty::Known(Type::Expression);
+                for e in v {
+                    result = self.infer(e, s);
+                }
+                result
+            }
+            Expr::For(var, begin, end, core, s) => {
+                self.infer_expecting(begin, Type::Number, s);
+                self.infer_expecting(end, Type::Number, s);
+                if let Some(var_name) = self.name(var, s) {
+                    let previous_values = self.bind(vec![(var_name, Ty::Known(Type::Number))]);
+                    self.infer(core, s);
+                    self.restore(previous_values);
+                }
+                Ty::Known(Type::Expression)
+            }
+            Expr::While(b, core, s) => {
+                self.infer_expecting(b, Type::Bool, s);
+                self.infer(core, s);
+                Ty::Known(Type::Expression)
+            }
+            Expr::Define(name, params, annotation, core, s) => {
+                let func_name = match self.name(name, s) {
+                    Some(func_name) => func_name,
+                    None => return Ty::Known(Type::Expression),
+                };
+                let arg_names: Vec<String> = params.iter().map(|p| p.get_name()).collect();
+                let param_types: Vec<Ty> = params
+                    .iter()
+                    .map(|p| match p.is_rest() {
+                        true => Ty::Known(Type::List),
+                        false => self.annotated(&p.get_annotation(), s),
+                    })
+                    .collect();
+                let arg_types: Vec<Ty> = params
+                    .iter()
+                    .zip(param_types.iter())
+                    .filter(|(p, _)| !p.is_rest())
+                    .map(|(_, t)| t.clone())
+                    .collect();
+                let ret = self.annotated(annotation, s);
+
+                // The function is known in its own core, so that it can be recursive
+                let signature = Signature {
+                    generics: vec![],
+                    args: arg_types.clone(),
+                    arity: arity(params),
+                    ret: ret.clone(),
+                };
+                let previous_function = self.functions.insert(func_name.clone(), signature);
+                let previous_values =
+                    self.bind(arg_names.into_iter().zip(param_types.clone()).collect());
+                self.functions_checked += 1;
+                for (p, t) in params.iter().zip(param_types.iter()) {
+                    if let Some(default) = p.get_default() {
+                        let default_type = self.infer(&default, s);
+                        self.expect(&default_type, t, s);
+                    }
+                }
+                let core_type = self.infer(core, s);
+                self.functions_checked -= 1;
+                self.expect(&core_type, &ret, s);
+                self.restore(previous_values);
+                if let Some(previous_function) = previous_function {
+                    self.functions.insert(func_name.clone(), previous_function);
+                }
+
+                let free = self.free_in_variables();
+                let generics = arg_types
+                    .iter()
+                    .chain(std::iter::once(&ret))
+                    .filter_map(|t| match self.resolve(t) {
+                        Ty::Unknown(i) if !free.contains(&i) => Some(i),
+                        _ => None,
+                    })
+                    .collect();
+                let signature = Signature {
+                    generics,
+                    args: arg_types,
+                    arity: arity(params),
+                    ret,
+                };
+                self.functions.insert(func_name.clone(), signature.clone());
+                self.signatures.push((func_name, signature));
+                Ty::Known(Type::Expression)
+            }
+            Expr::Call(name, args, s) => {
+                let arg_types: Vec<Ty> = args.iter().map(|a| self.infer(a, s)).collect();
+                let func_name = match self.name(name, s) {
+                    Some(func_name) => func_name,
+                    None => return self.fresh(),
+                };
+                let signature = match self.functions.get(&func_name).cloned() {
+                    Some(signature) => signature,
+                    None if self.functions_checked > 0 => return self.fresh(),
+                    None => {
+                        self.errors.push(LangError::new_undefined_variable_error(
+                            func_name,
+                            s.to_string(),
+                        ));
+                        return self.fresh();
+                    }
+                };
+                let (expected_args, ret) = self.instantiate(&signature);
+                let (min, max) = signature.arity;
+                if arg_types.len() < min || max.is_some_and(|max| arg_types.len() > max) {
+                    self.errors.push(LangError::new_arity_error(
+                        func_name,
+                        min,
+                        max,
+                        arg_types.len(),
+                        s.to_string(),
+                    ));
+                }
+                for (t, expected) in arg_types.iter().zip(expected_args.iter()) {
+                    self.expect(t, expected, s);
+                }
+                ret
+            }
+            Expr::Print(v, _) => {
+                v.iter().for_each(|e| {
+                    self.infer(e, parent_str);
+                });
+                Ty::Known(Type::Expression)
+            }
+            // The value read can be a number or a string
+            Expr::Input => self.fresh(),
+            Expr::Args => Ty::Known(Type::List),
+            Expr::Env(name, s) => {
+                self.infer_expecting(name, Type::Str, s);
+                Ty::Known(Type::Enum("option".to_string()))
+            }
+            // The program stops, so the result can be used as any type
+            Expr::Exit(code, s) => {
+                self.infer_expecting(code, Type::Number, s);
+                self.fresh()
+            }
+            Expr::List(v) => {
+                v.iter().for_each(|e| {
+                    self.infer(e, parent_str);
+                });
+                Ty::Known(Type::List)
+            }
+            Expr::Enum(enum_name, variants, s) => {
+                if let Some(str_enum_name) = self.name(enum_name, s) {
+                    self.enums.insert(str_enum_name, variants.clone());
+                }
+                Ty::Known(Type::Expression)
+            }
+            Expr::EnumElement(x) => self.infer_construct(x, 0, x),
+            Expr::EnumConstruct(x, args, s) => {
+                args.iter().for_each(|e| {
+                    self.infer(e, s);
+                });
+                self.infer_construct(x, args.len(), s)
+            }
+            Expr::Variants(enum_name, s) => {
+                if let Some(str_enum_name) = self.name(enum_name, s) {
+                    if !self.enums.contains_key(&str_enum_name) {
+                        self.errors.push(LangError::new_undefined_variable_error(
+                            str_enum_name,
+                            s.to_string(),
+                        ));
+                    }
+                }
+                Ty::Known(Type::List)
+            }
+            Expr::Match(x, arms, s) => {
+                let t = self.infer(x, s);
+                let result = self.fresh();
+                for (pattern, body) in arms.iter() {
+                    let mut bindings = Vec::new();
+                    self.infer_pattern(pattern, &t, &mut bindings, s);
+                    let previous_values = self.bind(bindings);
+                    let body_type = self.infer(body, s);
+                    self.expect(&body_type, &result, s);
+                    self.restore(previous_values);
+                }
+                result
+            }
+        }
+    }
+
+    /// Returns the type of the enum item `ENUM_NAME.ITEM` built with `nb_fields` fields
+    fn infer_construct(&mut self, name: &str, nb_fields: usize, expr_str: &str) -> Ty {
+        let (enum_name, variant_name) = name.split_once('.').unwrap_or((name, ""));
+        let variant = self
+            .enums
+            .get(enum_name)
+            .and_then(|variants| variants.iter().find(|v| v.get_name() == variant_name))
+            .cloned();
+        match variant {
+            Some(v) => {
+                if v.get_fields().len() != nb_fields {
+                    self.errors.push(LangError::new_arity_error(
+                        name.to_string(),
+                        v.get_fields().len(),
+                        Some(v.get_fields().len()),
+                        nb_fields,
+                        expr_str.to_string(),
+                    ));
+                }
+                Ty::Known(Type::Enum(enum_name.to_string()))
+            }
+            None => {
+                self.errors.push(LangError::new_undefined_variable_error(
+                    name.to_string(),
+                    expr_str.to_string(),
+                ));
+                self.fresh()
+            }
+        }
+    }
+
+    /// Checks that the pattern can match a value of type `t`
+    /// and pushes the types of the names it binds into `bindings`
+    fn infer_pattern(
+        &mut self,
+        pattern: &Pattern,
+        t: &Ty,
+        bindings: &mut Vec<(String, Ty)>,
+        expr_str: &str,
+    ) {
+        match pattern {
+            Pattern::Wildcard => {}
+            Pattern::Binding(name) => bindings.push((name.to_string(), t.clone())),
+            Pattern::Literal(e) => {
+                let literal_type = self.infer(e, expr_str);
+                self.expect(&literal_type, t, expr_str);
+            }
+            Pattern::List(patterns, rest) => {
+                self.expect(&Ty::Known(Type::List), t, expr_str);
+                for p in patterns.iter() {
+                    let element_type = self.fresh();
+                    self.infer_pattern(p, &element_type, bindings, expr_str);
+                }
+                if let Some(rest) = rest {
+                    bindings.push((rest.to_string(), Ty::Known(Type::List)));
+                }
+            }
+            Pattern::Variant(name, patterns) => {
+                let variant_type = self.infer_construct(name, patterns.len(), expr_str);
+                self.expect(&variant_type, t, expr_str);
+                for p in patterns.iter() {
+                    let field_type = self.fresh();
+                    self.infer_pattern(p, &field_type, bindings, expr_str);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests_checker {
+    use crate::checker::*;
+
+    fn check(s: &str) -> Vec<LangError> {
+        Checker::new().check(&Expr::token_tree(s).unwrap())
+    }
+
+    #[test]
+    fn test_check_ok() {
+        assert!(check("((let x 1) (for i 1 10 (set x (* x i))) x)").is_empty());
+    }
+
+    #[test]
+    fn test_check_add_str() {
+        assert_eq!(check("(+ \"a\" 1)").len(), 1);
+    }
+
+    #[test]
+    fn test_check_if_condition() {
+        assert_eq!(check("(if 1 2 3)").len(), 1);
+    }
+
+    #[test]
+    fn test_check_set_undeclared() {
+        assert_eq!(check("(set y 1)").len(), 1);
+    }
+
+    #[test]
+    fn test_check_dynamic_scope() {
+        // The variables and functions of a core are the ones defined when it is called
+        assert!(check("((def f x (+ x y)) (let y 1) (print (call f 2)))").is_empty());
+        assert!(check("((def f x (call g x)) (def g x (* x 2)) (call f 1))").is_empty());
+        assert!(check("(def f x (set y x))").is_empty());
+        assert_eq!(check("((def f x x) (+ y 1))").len(), 1);
+    }
+
+    #[test]
+    fn test_check_let_type() {
+        assert_eq!(check("((let x \"a\") (set x 1))").len(), 1);
+    }
+
+    #[test]
+    fn test_check_recursion() {
+        let mut checker = Checker::new();
+        let tree = Expr::token_tree(
+            "((def fact n (if (<= n 1) 1 (* n (call fact (- n 1))))) (call fact 4))",
+        )
+        .unwrap();
+        assert!(checker.check(&tree).is_empty());
+        assert_eq!(checker.signatures(), vec!["fact: (Number) -> Number"]);
+    }
+
+    #[test]
+    fn test_check_call_type() {
+        assert_eq!(
+            check("((def square x (* x x)) (call square \"a\"))").len(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_check_call_arity() {
+        assert_eq!(check("((def square x (* x x)) (call square 1 2))").len(), 1);
+    }
+
+    #[test]
+    fn test_check_generic_function() {
+        assert!(check("((def id x x) (+ (call id 1) 1) (print (call id \"a\")))").is_empty());
+    }
+
+    #[test]
+    fn test_check_enums() {
+        assert_eq!(
+            check("((enum days mon tue) (enum color red) (= days.mon color.red))").len(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_check_reports_all_errors() {
+        assert_eq!(check("((+ \"a\" 1) (if 1 2 3) (set y 1))").len(), 3);
+    }
+
+    #[test]
+    fn test_check_annotations() {
+        assert!(check(
+            "((let x : int 3) (def add (a : int) (b : int) -> int (+ a b)) (call add x 1))"
+        )
+        .is_empty());
+        assert_eq!(check("(let x : str 3)").len(), 1);
+        assert_eq!(
+            check("((def add (a : int) b (+ a b)) (call add \"a\" 1))").len(),
+            1
+        );
+        assert_eq!(check("(def f (a : str) -> int a)").len(), 1);
+        assert_eq!(check("(let x : integer 3)").len(), 1);
+        assert!(check("((enum color red) (def f (c : color) c))").is_empty());
+    }
+
+    #[test]
+    fn test_check_defaults_and_rest() {
+        let mut checker = Checker::new();
+        let tree =
+            Expr::token_tree("((def f a (b 1) & r (+ a b)) (call f 1) (call f 1 2 3 4))").unwrap();
+        assert!(checker.check(&tree).is_empty());
+        assert_eq!(
+            checker.signatures(),
+            vec!["f: (Number, Number, & List) -> Number"]
+        );
+        assert_eq!(check("((def f a (b 1) (+ a b)) (call f))").len(), 1);
+        assert_eq!(check("((def f a (b \"x\") (+ a b)) (call f 1))").len(), 1);
+    }
+
+    #[test]
+    fn test_check_exit() {
+        assert!(check("(+ 1 (if (> 2 1) 2 (exit 1)))").is_empty());
+        assert_eq!(check("(exit \"a\")").len(), 1);
+    }
+
+    #[test]
+    fn test_check_env() {
+        assert!(check("(match (env \"HOME\") ((option.some h) h) (option.none \"\"))").is_empty());
+        assert_eq!(check("(+ 1 (env \"HOME\"))").len(), 1);
+        assert_eq!(check("(+ 1 (args))").len(), 1);
+    }
+}
@@ -0,0 +1,377 @@
+use rusil::formatter::DEFAULT_WIDTH;
+use rusil::tracer::Filter;
+
+/// Text printed by `rusil --help`
+pub const USAGE: &str = "Usage: rusil [COMMAND] [OPTIONS]
+
+Commands:
+    run [RUN OPTIONS] SOURCE [ARGS...]
+                            Execute the program (default command)
+    check SOURCE            Type check the program without executing it
+    fmt [--check] [--width N] SOURCE
+                            Print the program formatted, or only check it is
+    ast [--json] SOURCE     Print the token tree of the program, as an
+                            S-expression or as JSON with the positions
+    repl                    Read and evaluate expressions interactively
+    debug SOURCE [ARGS...]  Execute the program step by step
+    test [--coverage] [--lcov FILE] [PATHS...]
+                            Execute the test_*.rsl files of the paths (default: .),
+                            and print the lines they covered, or write them
+                            to the file in the lcov format
+
+SOURCE is one of:
+    FILE                    Read the program from the file
+    -                       Read the program from the standard input
+    -e '(EXPR)'             Evaluate the expression
+
+Run options:
+    --trace                 Print each evaluated expression and its value on
+                            the standard error
+    --trace-calls           Print only the function calls
+    --trace-function NAME   Print only the calls of the function, and the
+                            expressions of its body
+    --profile               Print the time spent in each function and loop on
+                            the standard error
+    --profile-stacks FILE   Also write the collapsed stacks of the profile to
+                            the file, for flame graph tools
+
+Options:
+    -h, --help              Print this help
+";
+
+/// Names of the commands
+const COMMANDS: [&str; 7] = ["run", "check", "fmt", "ast", "repl", "test", "debug"];
+
+/// Where the program is read from
+#[derive(Debug, PartialEq)]
+pub enum Source {
+    File(String),
+    Stdin,
+    Inline(String),
+}
+
+/// Command given on the command line
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Help,
+    /// The source, the extra arguments passed on to the program, and the options
+    Run(Source, Vec<String>, RunOptions),
+    Check(Source),
+    /// The source, whether to only check that it is formatted, and the maximum line width
+    Fmt(Source, bool, usize),
+    /// The source, and whether to print JSON rather than an S-expression
+    Ast(Source, bool),
+    Repl,
+    /// The paths, whether to print the coverage, and the file where to write it as lcov
+    Test(Vec<String>, bool, Option<String>),
+    /// The source, and the extra arguments passed on to the program
+    Debug(Source, Vec<String>),
+}
+
+impl Command {
+    /// Returns the command associated with the arguments, without the program name
+    /// If they are not valid, returns a message explaining why
+    pub fn parse(args: &[String]) -> Result<Self, String> {
+        let (command, rest) = match args.split_first() {
+            Some((command, rest)) => (command.as_str(), rest),
+            None => return Ok(Command::Help),
+        };
+        let is_help = |a: &String| a == "-h" || a == "--help";
+        // Arguments after the source are left to the program
+        if is_help(&args[0]) || (COMMANDS.contains(&command) && rest.first().is_some_and(is_help)) {
+            return Ok(Command::Help);
+        }
+        match command {
+            "run" => {
+                let (options, rest) = RunOptions::parse(rest)?;
+                let (source, rest) = Source::parse(rest)?;
+                Ok(Command::Run(source, rest.to_vec(), options))
+            }
+            "check" => Ok(Command::Check(Source::parse_only(command, rest)?)),
+            "fmt" => {
+                let mut check = false;
+                let mut width = DEFAULT_WIDTH;
+                let mut rest = rest;
+                loop {
+                    match rest {
+                        [option, tail @ ..] if option == "--check" => {
+                            check = true;
+                            rest = tail;
+                        }
+                        [option, n, tail @ ..] if option == "--width" => {
+                            width = n.parse().map_err(|_| format!("Invalid width: {}", n))?;
+                            rest = tail;
+                        }
+                        _ => break,
+                    }
+                }
+                Ok(Command::Fmt(
+                    Source::parse_only(command, rest)?,
+                    check,
+                    width,
+                ))
+            }
+            "ast" => match rest {
+                [json, rest @ ..] if json == "--json" => {
+                    Ok(Command::Ast(Source::parse_only(command, rest)?, true))
+                }
+                _ => Ok(Command::Ast(Source::parse_only(command, rest)?, false)),
+            },
+            "repl" if rest.is_empty() => Ok(Command::Repl),
+            "repl" => Err("\"repl\" takes no argument".to_string()),
+            "debug" => {
+                let (source, rest) = Source::parse(rest)?;
+                Ok(Command::Debug(source, rest.to_vec()))
+            }
+            "test" => {
+                let mut coverage = false;
+                let mut lcov = None;
+                let mut rest = rest;
+                loop {
+                    match rest {
+                        [option, tail @ ..] if option == "--coverage" => {
+                            coverage = true;
+                            rest = tail;
+                        }
+                        [option, file, tail @ ..] if option == "--lcov" => {
+                            lcov = Some(file.to_string());
+                            rest = tail;
+                        }
+                        [option] if option == "--lcov" => {
+                            return Err("--lcov expects a file".to_string())
+                        }
+                        _ => break,
+                    }
+                }
+                let paths = match rest {
+                    [] => vec![".".to_string()],
+                    paths => paths.to_vec(),
+                };
+                Ok(Command::Test(paths, coverage, lcov))
+            }
+            // `rusil FILE ARGS...` is short for `rusil run FILE ARGS...`
+            _ => {
+                let (options, rest) = RunOptions::parse(args)?;
+                let (source, rest) = Source::parse(rest)?;
+                Ok(Command::Run(source, rest.to_vec(), options))
+            }
+        }
+    }
+}
+
+/// Options of `rusil run`, given before the source
+#[derive(Debug, Default, PartialEq)]
+pub struct RunOptions {
+    /// Expressions to print on the standard error while the program is executed
+    pub trace: Option<Filter>,
+    /// Whether to print the profile of the program on the standard error
+    pub profile: bool,
+    /// File where the collapsed stacks of the profile are written
+    pub stacks: Option<String>,
+}
+
+impl RunOptions {
+    /// Returns the options at the start of the arguments, and the remaining arguments
+    fn parse(args: &[String]) -> Result<(Self, &[String]), String> {
+        let mut options = RunOptions::default();
+        let mut rest = args;
+        loop {
+            match rest {
+                [option, tail @ ..] if option == "--trace" => {
+                    options.trace = Some(Filter::All);
+                    rest = tail;
+                }
+                [option, tail @ ..] if option == "--trace-calls" => {
+                    options.trace = Some(Filter::Calls);
+                    rest = tail;
+                }
+                [option, name, tail @ ..] if option == "--trace-function" => {
+                    options.trace = Some(Filter::Function(name.to_string()));
+                    rest = tail;
+                }
+                [option, tail @ ..] if option == "--profile" => {
+                    options.profile = true;
+                    rest = tail;
+                }
+                [option, file, tail @ ..] if option == "--profile-stacks" => {
+                    options.profile = true;
+                    options.stacks = Some(file.to_string());
+                    rest = tail;
+                }
+                [option] if option == "--trace-function" || option == "--profile-stacks" => {
+                    return Err(format!("{} expects an argument", option))
+                }
+                _ if options.trace.is_some() && options.profile => {
+                    return Err("--trace and --profile cannot be combined".to_string())
+                }
+                _ => return Ok((options, rest)),
+            }
+        }
+    }
+}
+
+impl Source {
+    /// Returns the source given at the start of the arguments, and the remaining arguments
+    fn parse(args: &[String]) -> Result<(Self, &[String]), String> {
+        match args {
+            [e, code, rest @ ..] if e == "-e" => Ok((Source::Inline(code.to_string()), rest)),
+            [e] if e == "-e" => Err("-e expects an expression".to_string()),
+            [stdin, rest @ ..] if stdin == "-" => Ok((Source::Stdin, rest)),
+            [option, ..] if option.starts_with('-') => Err(format!("Unknown option {}", option)),
+            [file, rest @ ..] => Ok((Source::File(file.to_string()), rest)),
+            [] => Err("Expected a file, - or -e '(EXPR)'".to_string()),
+        }
+    }
+
+    /// Returns the source, when it must be the only argument of the command
+    fn parse_only(command: &str, args: &[String]) -> Result<Self, String> {
+        match Source::parse(args)? {
+            (source, []) => Ok(source),
+            (_, rest) => Err(format!(
+                "Unexpected argument to \"{}\": {}",
+                command, rest[0]
+            )),
+        }
+    }
+
+    /// Returns the name of the source, used in messages
+    pub fn name(&self) -> String {
+        match self {
+            Source::File(file) => file.to_string(),
+            Source::Stdin => "<stdin>".to_string(),
+            Source::Inline(_) => "<expression>".to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests_cli {
+    use crate::cli::*;
+
+    fn parse(args: &[&str]) -> Result<Command, String> {
+        Command::parse(&args.iter().map(|a| a.to_string()).collect::<Vec<String>>())
+    }
+
+    #[test]
+    fn test_parse_run() {
+        assert_eq!(
+            parse(&["run", "f.rsl", "a", "-b"]),
+            Ok(Command::Run(
+                Source::File("f.rsl".to_string()),
+                vec!["a".to_string(), "-b".to_string()],
+                RunOptions::default()
+            ))
+        );
+        assert_eq!(
+            parse(&["f.rsl"]),
+            Ok(Command::Run(
+                Source::File("f.rsl".to_string()),
+                vec![],
+                RunOptions::default()
+            ))
+        );
+        assert_eq!(
+            parse(&["-e", "(+ 1 2)"]),
+            Ok(Command::Run(
+                Source::Inline("(+ 1 2)".to_string()),
+                vec![],
+                RunOptions::default()
+            ))
+        );
+        assert_eq!(
+            parse(&["run", "-"]),
+            Ok(Command::Run(Source::Stdin, vec![], RunOptions::default()))
+        );
+        assert_eq!(
+            parse(&["--trace-function", "fact", "f.rsl", "--trace"]),
+            Ok(Command::Run(
+                Source::File("f.rsl".to_string()),
+                vec!["--trace".to_string()],
+                RunOptions {
+                    trace: Some(Filter::Function("fact".to_string())),
+                    ..RunOptions::default()
+                }
+            ))
+        );
+        assert_eq!(
+            parse(&["run", "--profile-stacks", "out.txt", "-"]),
+            Ok(Command::Run(
+                Source::Stdin,
+                vec![],
+                RunOptions {
+                    profile: true,
+                    stacks: Some("out.txt".to_string()),
+                    ..RunOptions::default()
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn test_parse_commands() {
+        assert_eq!(parse(&[]), Ok(Command::Help));
+        assert_eq!(parse(&["check", "--help"]), Ok(Command::Help));
+        assert_eq!(
+            parse(&["check", "f.rsl"]),
+            Ok(Command::Check(Source::File("f.rsl".to_string())))
+        );
+        assert_eq!(
+            parse(&["f.rsl", "--help"]),
+            Ok(Command::Run(
+                Source::File("f.rsl".to_string()),
+                vec!["--help".to_string()],
+                RunOptions::default()
+            ))
+        );
+        assert_eq!(
+            parse(&["fmt", "--width", "60", "--check", "-"]),
+            Ok(Command::Fmt(Source::Stdin, true, 60))
+        );
+        assert_eq!(
+            parse(&["fmt", "f.rsl"]),
+            Ok(Command::Fmt(
+                Source::File("f.rsl".to_string()),
+                false,
+                DEFAULT_WIDTH
+            ))
+        );
+        assert_eq!(
+            parse(&["ast", "--json", "-"]),
+            Ok(Command::Ast(Source::Stdin, true))
+        );
+        assert_eq!(parse(&["repl"]), Ok(Command::Repl));
+        assert_eq!(
+            parse(&["debug", "f.rsl", "a"]),
+            Ok(Command::Debug(
+                Source::File("f.rsl".to_string()),
+                vec!["a".to_string()]
+            ))
+        );
+        assert_eq!(
+            parse(&["test"]),
+            Ok(Command::Test(vec![".".to_string()], false, None))
+        );
+        assert_eq!(
+            parse(&["test", "--lcov", "lcov.info", "--coverage", "code"]),
+            Ok(Command::Test(
+                vec!["code".to_string()],
+                true,
+                Some("lcov.info".to_string())
+            ))
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(parse(&["run"]).is_err());
+        assert!(parse(&["run", "-e"]).is_err());
+        assert!(parse(&["run", "--trace-function"]).is_err());
+        assert!(parse(&["--trace", "--verbose", "f.rsl"]).is_err());
+        assert!(parse(&["--trace", "--profile", "f.rsl"]).is_err());
+        assert!(parse(&["check", "f.rsl", "g.rsl"]).is_err());
+        assert!(parse(&["fmt", "--width"]).is_err());
+        assert!(parse(&["fmt", "--width", "wide", "f.rsl"]).is_err());
+        assert!(parse(&["repl", "f.rsl"]).is_err());
+        assert!(parse(&["test", "--lcov"]).is_err());
+    }
+}
@@ -0,0 +1,291 @@
+use crate::capabilities::*;
+use crate::enums::*;
+use crate::errors::*;
+use crate::functions::*;
+use crate::native::*;
+use crate::tokens::*;
+use std::collections::{HashMap, HashSet};
+use std::io::{BufRead, BufReader, Write};
+use std::time::{Duration, Instant};
+
+/// Default number of nested function calls after which the program is stopped
+pub(crate) const MAX_CALL_DEPTH: usize = 1000;
+
+/// Default size in bytes of the stack an evaluation can use, half of the stack of the threads
+/// spawned by the standard library, so that no program overflows the stack of the thread
+/// running it, whatever its depth
+pub(crate) const MAX_STACK: usize = 1024 * 1024;
+
+/// Returns the address of the top of the stack of the current thread, approximately
+#[inline(never)]
+fn stack_address() -> usize {
+    let marker = 0u8;
+    std::hint::black_box(&marker) as *const u8 as usize
+}
+
+/// Observer of the evaluation, like the debugger or the profiler
+pub(crate) trait Hook {
+    /// Called before evaluating the expression, with the scope it is evaluated in
+    /// An error stops the program
+    fn before(
+        &mut self,
+        e: &Expr,
+        variables: &mut HashMap<String, Expr>,
+        functions: &mut HashMap<String, Function>,
+        enums: &mut HashMap<String, Vec<Variant>>,
+        context: &mut Context,
+    ) -> Result<(), LangError>;
+
+    /// Called after evaluating the expression, with its result
+    fn after(&mut self, e: &Expr, result: &Result<Expr, LangError>, context: &mut Context);
+}
+
+/// State of the interpreter that is not part of the program itself
+pub struct Context {
+    /// Extra arguments given on the command line, returned by `(args)`
+    args: Vec<String>,
+    /// Stream read by `(input)`
+    input: Box<dyn BufRead>,
+    /// Stream written by `(print ...)`
+    output: Box<dyn Write>,
+    /// Rust functions callable with `(call NAME ...)`
+    natives: HashMap<String, Native>,
+    /// Number of expressions the program can still evaluate, unlimited if `None`
+    fuel: Option<u64>,
+    /// Time given to each evaluation, unlimited if `None`
+    timeout: Option<Duration>,
+    /// End of the current evaluation, computed from `timeout`
+    deadline: Option<Instant>,
+    /// Maximum size in bytes of the values created by the program, together, unlimited if `None`
+    allocation_budget: Option<usize>,
+    /// Size in bytes of the values created since the allocation budget was set
+    allocated: usize,
+    /// Maximum number of nested function calls, unlimited if `None`
+    max_call_depth: Option<usize>,
+    /// Maximum size in bytes of the stack used by an evaluation, unlimited if `None`
+    max_stack: Option<usize>,
+    /// Address of the top of the stack when the current evaluation started
+    stack_start: Option<usize>,
+    /// Capabilities the program can use
+    capabilities: HashSet<Capability>,
+    /// Names of the functions being called, the innermost last
+    calls: Vec<String>,
+    /// Observers called around the evaluation of each expression
+    hooks: Vec<Box<dyn Hook>>,
+}
+
+impl Context {
+    pub fn new(args: Vec<String>, input: Box<dyn BufRead>, output: Box<dyn Write>) -> Self {
+        Context {
+            args,
+            input,
+            output,
+            natives: HashMap::new(),
+            fuel: None,
+            timeout: None,
+            deadline: None,
+            allocation_budget: None,
+            allocated: 0,
+            max_call_depth: Some(MAX_CALL_DEPTH),
+            max_stack: Some(MAX_STACK),
+            stack_start: None,
+            capabilities: Capability::all().into_iter().collect(),
+            calls: Vec::new(),
+            hooks: Vec::new(),
+        }
+    }
+
+    /// Returns a context reading the standard input and writing the standard output
+    pub fn stdio(args: Vec<String>) -> Self {
+        Context::new(
+            args,
+            Box::new(BufReader::new(std::io::stdin())),
+            Box::new(std::io::stdout()),
+        )
+    }
+
+    pub(crate) fn get_args(&self) -> &[String] {
+        &self.args
+    }
+
+    pub fn get_input(&mut self) -> &mut dyn BufRead {
+        self.input.as_mut()
+    }
+
+    pub fn get_output(&mut self) -> &mut dyn Write {
+        self.output.as_mut()
+    }
+
+    pub(crate) fn register(&mut self, native: Native) {
+        self.natives.insert(native.get_name(), native);
+    }
+
+    pub(crate) fn get_native(&self, name: &str) -> Option<&Native> {
+        self.natives.get(name)
+    }
+
+    pub(crate) fn set_fuel(&mut self, fuel: Option<u64>) {
+        self.fuel = fuel;
+    }
+
+    pub(crate) fn get_fuel(&self) -> Option<u64> {
+        self.fuel
+    }
+
+    pub(crate) fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+    }
+
+    /// Starts the clock of an evaluation, and measures its stack from its first expression
+    pub(crate) fn start(&mut self) {
+        self.deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+        self.stack_start = None;
+    }
+
+    /// Consumes one step of the budget, before evaluating an expression
+    /// Returns an error if the budget is exhausted, the timeout has expired, or the stack used
+    /// is larger than allowed
+    pub(crate) fn step(&mut self) -> Result<(), LangError> {
+        // The stack grows down from where the evaluation started
+        let here = stack_address();
+        let used = self.stack_start.get_or_insert(here).saturating_sub(here);
+        if let Some(max) = self.max_stack.filter(|max| used > *max) {
+            return Err(LangError::new_resource_exhausted(
+                "stack".to_string(),
+                format!(
+                    "The program nests expressions or calls using more than {} bytes of the stack",
+                    max
+                ),
+            ));
+        }
+        if let Some(fuel) = self.fuel {
+            if fuel == 0 {
+                return Err(LangError::new_resource_exhausted(
+                    "fuel".to_string(),
+                    "The program evaluated more expressions than allowed".to_string(),
+                ));
+            }
+            self.fuel = Some(fuel - 1);
+        }
+        if self
+            .deadline
+            .is_some_and(|deadline| Instant::now() > deadline)
+        {
+            return Err(LangError::new_resource_exhausted(
+                "timeout".to_string(),
+                format!(
+                    "The program ran for more than {} ms",
+                    self.timeout.unwrap_or_default().as_millis()
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn set_allocation_budget(&mut self, allocation_budget: Option<usize>) {
+        self.allocation_budget = allocation_budget;
+        self.allocated = 0;
+    }
+
+    /// Returns the value created by the program, counting its size in the allocation budget
+    /// The values no longer used are not given back to it
+    /// If the values created become larger than the budget, returns an error instead
+    pub(crate) fn allocate(&mut self, value: Expr, expr_str: &str) -> Result<Expr, LangError> {
+        let size = value.size();
+        self.allocated = self.allocated.saturating_add(size);
+        match self.allocation_budget {
+            Some(budget) if self.allocated > budget => Err(LangError::new_resource_exhausted(
+                "allocations".to_string(),
+                format!(
+                    "{} creates a value of about {} bytes, bringing the values created \
+                     to {} bytes, more than the budget of {} bytes",
+                    expr_str, size, self.allocated, budget
+                ),
+            )),
+            _ => Ok(value),
+        }
+    }
+
+    pub(crate) fn set_max_call_depth(&mut self, max_call_depth: Option<usize>) {
+        self.max_call_depth = max_call_depth;
+    }
+
+    /// Limits the size in bytes of the stack used by each evaluation, 1 MiB by default
+    pub fn set_max_stack(&mut self, max_stack: Option<usize>) {
+        self.max_stack = max_stack;
+    }
+
+    pub(crate) fn set_capabilities(&mut self, capabilities: &[Capability]) {
+        self.capabilities = capabilities.iter().copied().collect();
+    }
+
+    /// Returns an error if the capability is not allowed
+    pub(crate) fn require(&self, capability: Capability, expr_str: &str) -> Result<(), LangError> {
+        if self.capabilities.contains(&capability) {
+            Ok(())
+        } else {
+            Err(LangError::new_permission_denied(
+                capability,
+                expr_str.to_string(),
+            ))
+        }
+    }
+
+    pub(crate) fn get_calls(&self) -> &[String] {
+        &self.calls
+    }
+
+    /// Enters the function, or returns an error if too many functions are being called
+    pub(crate) fn push_call(&mut self, name: &str, expr_str: &str) -> Result<(), LangError> {
+        if let Some(max) = self.max_call_depth.filter(|max| self.calls.len() >= *max) {
+            return Err(LangError::new_resource_exhausted(
+                "call depth".to_string(),
+                format!("{} calls more than {} nested functions", expr_str, max),
+            ));
+        }
+        self.calls.push(name.to_string());
+        Ok(())
+    }
+
+    pub(crate) fn pop_call(&mut self) {
+        self.calls.pop();
+    }
+
+    pub(crate) fn add_hook(&mut self, hook: Box<dyn Hook>) {
+        self.hooks.push(hook);
+    }
+
+    /// Removes the hooks, so that they can be called with the context
+    /// The expressions they evaluate themselves are not observed
+    pub(crate) fn take_hooks(&mut self) -> Vec<Box<dyn Hook>> {
+        std::mem::take(&mut self.hooks)
+    }
+
+    /// Puts back the hooks removed by `take_hooks`
+    pub(crate) fn restore_hooks(&mut self, hooks: Vec<Box<dyn Hook>>) {
+        self.hooks = hooks;
+    }
+}
+
+/// Output stream whose content can be read after the program is executed
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub(crate) struct SharedOutput(std::rc::Rc<std::cell::RefCell<Vec<u8>>>);
+
+#[cfg(test)]
+impl SharedOutput {
+    pub(crate) fn contents(&self) -> String {
+        String::from_utf8_lossy(&self.0.borrow()).to_string()
+    }
+}
+
+#[cfg(test)]
+impl Write for SharedOutput {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,858 @@
+use crate::enums::*;
+use crate::errors::*;
+use crate::tokens::*;
+use serde::de::{self, DeserializeOwned, DeserializeSeed, IntoDeserializer, Visitor};
+use serde::ser::{self, Serialize};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt::Display;
+
+/// Enums declared by the program, whose field names give the order of the fields of structs
+type Enums<'a> = Option<&'a HashMap<String, Vec<Variant>>>;
+
+/// Returns the Rusil value of the Rust value
+///
+/// | Rust | Rusil |
+/// |---|---|
+/// | integers, `bool`, `char`, `String` | Number, Bool, Str |
+/// | `Vec`, tuples, tuple structs | List |
+/// | `Option` | `(option.some VALUE)` or `option.none` |
+/// | `()` | Nothing |
+/// | maps | List of `(list KEY VALUE)` |
+/// | `struct Point { x, y }` | `(Point.new X Y)` |
+/// | `enum Shape { Empty, Rect(w, h) }` | `Shape.Empty`, `(Shape.Rect W H)` |
+///
+/// The fields of structs are kept in the order of the Rust declaration:
+/// `Interpreter::to_expr` orders them like the enum declared by the program instead
+pub fn to_expr<T: Serialize + ?Sized>(value: &T) -> Result<Expr, LangError> {
+    value.serialize(Serializer(None))
+}
+
+/// Returns the Rust value of the Rusil value, with the conversions of `to_expr`
+pub fn from_expr<T: DeserializeOwned>(value: Expr) -> Result<T, LangError> {
+    T::deserialize(Deserializer(value, None))
+}
+
+/// Returns the Rusil value of the Rust value, the fields of the structs and enum items
+/// declared by the program being ordered by their names
+pub(crate) fn to_expr_with<T: Serialize + ?Sized>(
+    value: &T,
+    enums: &HashMap<String, Vec<Variant>>,
+) -> Result<Expr, LangError> {
+    value.serialize(Serializer(Some(enums)))
+}
+
+/// Returns the Rust value of the Rusil value, the fields of the structs and enum items
+/// declared by the program being matched by their names
+pub(crate) fn from_expr_with<T: DeserializeOwned>(
+    value: Expr,
+    enums: &HashMap<String, Vec<Variant>>,
+) -> Result<T, LangError> {
+    T::deserialize(Deserializer(value, Some(enums)))
+}
+
+/// Returns the names of the fields of the enum item, if the program declared it
+fn declared_fields(enums: Enums, enum_name: &str, variant: &str) -> Option<Vec<String>> {
+    enums?
+        .get(enum_name)?
+        .iter()
+        .find(|v| v.get_name() == variant)
+        .map(Variant::get_fields)
+}
+
+/// Returns a ConversionError if the declared fields are not the fields of the Rust value
+fn check_fields(
+    enum_name: &str,
+    variant: &str,
+    declared: &[String],
+    fields: &[&str],
+) -> Result<(), LangError> {
+    let mut sorted_declared: Vec<&str> = declared.iter().map(String::as_str).collect();
+    let mut sorted_fields = fields.to_vec();
+    sorted_declared.sort_unstable();
+    sorted_fields.sort_unstable();
+    if sorted_declared == sorted_fields {
+        Ok(())
+    } else {
+        Err(LangError::new_conversion_error(format!(
+            "{}.{} is declared with the fields ({}), but the Rust value has ({})",
+            enum_name,
+            variant,
+            declared.join(" "),
+            fields.join(" ")
+        )))
+    }
+}
+
+impl ser::Error for LangError {
+    fn custom<T: Display>(msg: T) -> Self {
+        LangError::new_conversion_error(msg.to_string())
+    }
+}
+
+impl de::Error for LangError {
+    fn custom<T: Display>(msg: T) -> Self {
+        LangError::new_conversion_error(msg.to_string())
+    }
+}
+
+/// Returns the number, or a ConversionError if it does not fit in a Rusil integer
+fn number<T: Copy + Display>(x: T) -> Result<Expr, LangError>
+where
+    i32: TryFrom<T>,
+{
+    i32::try_from(x).map(Expr::Number).map_err(|_| {
+        LangError::new_conversion_error(format!(
+            "{} does not fit in an integer (integers go from {} to {})",
+            x,
+            i32::MIN,
+            i32::MAX
+        ))
+    })
+}
+
+/// Returns `(ENUM_NAME.ITEM FIELD1 ... FIELDn)`
+fn enum_value(enum_name: &str, variant: &str, fields: Vec<Expr>) -> Expr {
+    Expr::EnumValue {
+        enum_name: enum_name.to_string(),
+        variant: variant.to_string(),
+        fields,
+    }
+}
+
+/// Serializes Rust values into Rusil values
+#[derive(Clone, Copy)]
+struct Serializer<'a>(Enums<'a>);
+
+impl<'a> Serializer<'a> {
+    fn convert<T: Serialize + ?Sized>(self, value: &T) -> Result<Expr, LangError> {
+        value.serialize(self)
+    }
+}
+
+impl<'a> ser::Serializer for Serializer<'a> {
+    type Ok = Expr;
+    type Error = LangError;
+    type SerializeSeq = ListSerializer<'a>;
+    type SerializeTuple = ListSerializer<'a>;
+    type SerializeTupleStruct = ListSerializer<'a>;
+    type SerializeTupleVariant = EnumValueSerializer<'a>;
+    type SerializeMap = MapSerializer<'a>;
+    type SerializeStruct = EnumValueSerializer<'a>;
+    type SerializeStructVariant = EnumValueSerializer<'a>;
+
+    fn serialize_bool(self, v: bool) -> Result<Expr, LangError> {
+        Ok(Expr::Bool(v))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<Expr, LangError> {
+        number(v)
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<Expr, LangError> {
+        number(v)
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<Expr, LangError> {
+        Ok(Expr::Number(v))
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<Expr, LangError> {
+        number(v)
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<Expr, LangError> {
+        number(v)
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<Expr, LangError> {
+        number(v)
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<Expr, LangError> {
+        number(v)
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<Expr, LangError> {
+        number(v)
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<Expr, LangError> {
+        self.serialize_f64(f64::from(v))
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<Expr, LangError> {
+        Err(LangError::new_conversion_error(format!(
+            "{} is not an integer, and Rusil has no floating-point numbers",
+            v
+        )))
+    }
+
+    fn serialize_char(self, v: char) -> Result<Expr, LangError> {
+        Ok(Expr::Str(v.to_string()))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<Expr, LangError> {
+        Ok(Expr::Str(v.to_string()))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<Expr, LangError> {
+        Ok(Expr::List(
+            v.iter().map(|b| Expr::Number(*b as i32)).collect(),
+        ))
+    }
+
+    fn serialize_none(self) -> Result<Expr, LangError> {
+        Ok(Expr::option(None))
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Expr, LangError> {
+        Ok(Expr::option(Some(self.convert(value)?)))
+    }
+
+    fn serialize_unit(self) -> Result<Expr, LangError> {
+        Ok(Expr::Empty)
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Expr, LangError> {
+        Ok(Expr::Empty)
+    }
+
+    fn serialize_unit_variant(
+        self,
+        name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+    ) -> Result<Expr, LangError> {
+        Ok(enum_value(name, variant, vec![]))
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<Expr, LangError> {
+        self.convert(value)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<Expr, LangError> {
+        Ok(enum_value(name, variant, vec![self.convert(value)?]))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<ListSerializer<'a>, LangError> {
+        Ok(ListSerializer(
+            Vec::with_capacity(len.unwrap_or_default()),
+            self,
+        ))
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<ListSerializer<'a>, LangError> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<ListSerializer<'a>, LangError> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<EnumValueSerializer<'a>, LangError> {
+        Ok(EnumValueSerializer {
+            value: enum_value(name, variant, Vec::with_capacity(len)),
+            names: Vec::with_capacity(len),
+            serializer: self,
+        })
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<MapSerializer<'a>, LangError> {
+        Ok(MapSerializer {
+            pairs: Vec::with_capacity(len.unwrap_or_default()),
+            key: None,
+            serializer: self,
+        })
+    }
+
+    fn serialize_struct(
+        self,
+        name: &'static str,
+        len: usize,
+    ) -> Result<EnumValueSerializer<'a>, LangError> {
+        // A struct is an enum with the single item `new`
+        self.serialize_tuple_variant(name, 0, "new", len)
+    }
+
+    fn serialize_struct_variant(
+        self,
+        name: &'static str,
+        variant_index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<EnumValueSerializer<'a>, LangError> {
+        self.serialize_tuple_variant(name, variant_index, variant, len)
+    }
+}
+
+/// Serializes sequences into lists
+struct ListSerializer<'a>(Vec<Expr>, Serializer<'a>);
+
+impl<'a> ser::SerializeSeq for ListSerializer<'a> {
+    type Ok = Expr;
+    type Error = LangError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), LangError> {
+        self.0.push(self.1.convert(value)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Expr, LangError> {
+        Ok(Expr::List(self.0))
+    }
+}
+
+impl<'a> ser::SerializeTuple for ListSerializer<'a> {
+    type Ok = Expr;
+    type Error = LangError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), LangError> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<Expr, LangError> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+impl<'a> ser::SerializeTupleStruct for ListSerializer<'a> {
+    type Ok = Expr;
+    type Error = LangError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), LangError> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<Expr, LangError> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+/// Serializes structs and enum items with fields into enum values
+struct EnumValueSerializer<'a> {
+    value: Expr,
+    /// Names of the fields pushed so far, if they have names
+    names: Vec<&'static str>,
+    serializer: Serializer<'a>,
+}
+
+impl<'a> EnumValueSerializer<'a> {
+    fn push<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), LangError> {
+        let value = self.serializer.convert(value)?;
+        if let Expr::EnumValue { fields, .. } = &mut self.value {
+            fields.push(value);
+        }
+        Ok(())
+    }
+
+    /// Returns the enum value, its fields in the order of the declaration of the program if any
+    fn end_named(self) -> Result<Expr, LangError> {
+        match self.value {
+            Expr::EnumValue {
+                enum_name,
+                variant,
+                fields,
+            } => {
+                let fields = match declared_fields(self.serializer.0, &enum_name, &variant) {
+                    Some(declared) => {
+                        check_fields(&enum_name, &variant, &declared, &self.names)?;
+                        let mut named: HashMap<&str, Expr> =
+                            self.names.into_iter().zip(fields).collect();
+                        declared
+                            .iter()
+                            .filter_map(|name| named.remove(name.as_str()))
+                            .collect()
+                    }
+                    None => fields,
+                };
+                Ok(Expr::EnumValue {
+                    enum_name,
+                    variant,
+                    fields,
+                })
+            }
+            value => Ok(value),
+        }
+    }
+}
+
+impl<'a> ser::SerializeTupleVariant for EnumValueSerializer<'a> {
+    type Ok = Expr;
+    type Error = LangError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), LangError> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Expr, LangError> {
+        Ok(self.value)
+    }
+}
+
+impl<'a> ser::SerializeStruct for EnumValueSerializer<'a> {
+    type Ok = Expr;
+    type Error = LangError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), LangError> {
+        self.names.push(key);
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Expr, LangError> {
+        self.end_named()
+    }
+}
+
+impl<'a> ser::SerializeStructVariant for EnumValueSerializer<'a> {
+    type Ok = Expr;
+    type Error = LangError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), LangError> {
+        self.names.push(key);
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Expr, LangError> {
+        self.end_named()
+    }
+}
+
+/// Serializes maps into lists of `(list KEY VALUE)`
+struct MapSerializer<'a> {
+    pairs: Vec<Expr>,
+    key: Option<Expr>,
+    serializer: Serializer<'a>,
+}
+
+impl<'a> ser::SerializeMap for MapSerializer<'a> {
+    type Ok = Expr;
+    type Error = LangError;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), LangError> {
+        self.key = Some(self.serializer.convert(key)?);
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), LangError> {
+        let key = self.key.take().unwrap_or(Expr::Empty);
+        let value = self.serializer.convert(value)?;
+        self.pairs.push(Expr::List(vec![key, value]));
+        Ok(())
+    }
+
+    fn end(self) -> Result<Expr, LangError> {
+        Ok(Expr::List(self.pairs))
+    }
+}
+
+/// Deserializes Rusil values into Rust values
+struct Deserializer<'a>(Expr, Enums<'a>);
+
+impl<'a> Deserializer<'a> {
+    fn error(&self, expected: &str) -> LangError {
+        LangError::new_conversion_error(format!("Expected {}, found {}", expected, self.0))
+    }
+}
+
+/// Returns true if the list is of the form `[(list KEY VALUE) ...]`
+fn is_map(items: &[Expr]) -> bool {
+    items
+        .iter()
+        .all(|e| matches!(e, Expr::List(pair) if pair.len() == 2))
+}
+
+/// Visits the fields of the enum value by their names if the program declared them,
+/// or else by their positions
+fn visit_fields<'de, V: Visitor<'de>>(
+    enums: Enums,
+    enum_name: &str,
+    variant: &str,
+    values: Vec<Expr>,
+    fields: &[&str],
+    visitor: V,
+) -> Result<V::Value, LangError> {
+    match declared_fields(enums, enum_name, variant) {
+        Some(declared) => {
+            check_fields(enum_name, variant, &declared, fields)?;
+            let pairs: Vec<Expr> = declared
+                .into_iter()
+                .zip(values)
+                .map(|(name, value)| Expr::List(vec![Expr::Str(name), value]))
+                .collect();
+            visitor.visit_map(MapDeserializer {
+                pairs: pairs.into_iter(),
+                value: None,
+                enums,
+            })
+        }
+        None => visitor.visit_seq(ListDeserializer(values.into_iter(), enums)),
+    }
+}
+
+impl<'a, 'de> de::Deserializer<'de> for Deserializer<'a> {
+    type Error = LangError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, LangError> {
+        match self.0 {
+            Expr::Number(x) => visitor.visit_i32(x),
+            Expr::Bool(x) => visitor.visit_bool(x),
+            Expr::Str(x) | Expr::Symbol(x) => visitor.visit_string(x),
+            Expr::List(items) => visitor.visit_seq(ListDeserializer(items.into_iter(), self.1)),
+            Expr::Empty => visitor.visit_unit(),
+            Expr::EnumValue {
+                enum_name,
+                variant,
+                mut fields,
+            } if enum_name == "option" => match (variant.as_str(), fields.pop()) {
+                ("some", Some(x)) => visitor.visit_some(Deserializer(x, self.1)),
+                _ => visitor.visit_none(),
+            },
+            Expr::EnumValue { fields, .. } => {
+                visitor.visit_seq(ListDeserializer(fields.into_iter(), self.1))
+            }
+            _ => Err(self.error("a value")),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, LangError> {
+        match self.0 {
+            Expr::EnumValue { ref enum_name, .. } if enum_name == "option" => {
+                self.deserialize_any(visitor)
+            }
+            Expr::Empty => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, LangError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, LangError> {
+        match self.0 {
+            Expr::List(items) if is_map(&items) => visitor.visit_map(MapDeserializer {
+                pairs: items.into_iter(),
+                value: None,
+                enums: self.1,
+            }),
+            _ => Err(self.error("a list of (list KEY VALUE)")),
+        }
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, LangError> {
+        match self.0 {
+            Expr::EnumValue {
+                enum_name,
+                variant,
+                fields: values,
+            } => visit_fields(self.1, &enum_name, &variant, values, fields, visitor),
+            Expr::List(ref items) if is_map(items) => self.deserialize_map(visitor),
+            _ => Err(self.error("an enum item with fields")),
+        }
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, LangError> {
+        match self.0 {
+            Expr::EnumValue {
+                enum_name,
+                variant,
+                fields,
+            } => visitor.visit_enum(EnumDeserializer {
+                enum_name,
+                variant,
+                fields,
+                enums: self.1,
+            }),
+            Expr::Str(variant) | Expr::Symbol(variant) => visitor.visit_enum(EnumDeserializer {
+                enum_name: name.to_string(),
+                variant,
+                fields: vec![],
+                enums: self.1,
+            }),
+            _ => Err(self.error("an enum item")),
+        }
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct seq tuple tuple_struct identifier ignored_any
+    }
+}
+
+/// Deserializes the elements of a list, or the fields of an enum item
+struct ListDeserializer<'a>(std::vec::IntoIter<Expr>, Enums<'a>);
+
+impl<'a, 'de> de::SeqAccess<'de> for ListDeserializer<'a> {
+    type Error = LangError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, LangError> {
+        self.0
+            .next()
+            .map(|e| seed.deserialize(Deserializer(e, self.1)))
+            .transpose()
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.0.len())
+    }
+}
+
+/// Deserializes a list of `(list KEY VALUE)`
+struct MapDeserializer<'a> {
+    pairs: std::vec::IntoIter<Expr>,
+    value: Option<Expr>,
+    enums: Enums<'a>,
+}
+
+impl<'a, 'de> de::MapAccess<'de> for MapDeserializer<'a> {
+    type Error = LangError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, LangError> {
+        match self.pairs.next() {
+            Some(Expr::List(pair)) => {
+                let mut pair = pair.into_iter();
+                let key = pair.next().unwrap_or(Expr::Empty);
+                self.value = pair.next();
+                seed.deserialize(Deserializer(key, self.enums)).map(Some)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn next_value_seed<T: DeserializeSeed<'de>>(&mut self, seed: T) -> Result<T::Value, LangError> {
+        let value = self.value.take().unwrap_or(Expr::Empty);
+        seed.deserialize(Deserializer(value, self.enums))
+    }
+}
+
+/// Deserializes an enum item, whose name is the name of the Rust variant
+struct EnumDeserializer<'a> {
+    enum_name: String,
+    variant: String,
+    fields: Vec<Expr>,
+    enums: Enums<'a>,
+}
+
+impl<'a, 'de> de::EnumAccess<'de> for EnumDeserializer<'a> {
+    type Error = LangError;
+    type Variant = FieldsDeserializer<'a>;
+
+    fn variant_seed<T: DeserializeSeed<'de>>(
+        self,
+        seed: T,
+    ) -> Result<(T::Value, FieldsDeserializer<'a>), LangError> {
+        let variant: de::value::StringDeserializer<LangError> =
+            self.variant.clone().into_deserializer();
+        Ok((seed.deserialize(variant)?, FieldsDeserializer(self)))
+    }
+}
+
+/// Deserializes the fields of an enum item
+struct FieldsDeserializer<'a>(EnumDeserializer<'a>);
+
+impl<'a, 'de> de::VariantAccess<'de> for FieldsDeserializer<'a> {
+    type Error = LangError;
+
+    fn unit_variant(self) -> Result<(), LangError> {
+        if self.0.fields.is_empty() {
+            Ok(())
+        } else {
+            Err(LangError::new_conversion_error(format!(
+                "Expected an enum item without fields, found {} field(s)",
+                self.0.fields.len()
+            )))
+        }
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(
+        mut self,
+        seed: T,
+    ) -> Result<T::Value, LangError> {
+        match (self.0.fields.pop(), self.0.fields.is_empty()) {
+            (Some(value), true) => seed.deserialize(Deserializer(value, self.0.enums)),
+            _ => Err(LangError::new_conversion_error(
+                "Expected an enum item with one field".to_string(),
+            )),
+        }
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(
+        self,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, LangError> {
+        visitor.visit_seq(ListDeserializer(self.0.fields.into_iter(), self.0.enums))
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, LangError> {
+        let e = self.0;
+        visit_fields(e.enums, &e.enum_name, &e.variant, e.fields, fields, visitor)
+    }
+}
+
+#[cfg(test)]
+mod tests_convert {
+    use crate::convert::*;
+    use crate::interpreter::*;
+    use serde::{Deserialize, Serialize};
+    use std::collections::BTreeMap;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Config {
+        name: String,
+        port: u16,
+        tags: Vec<String>,
+        debug: Option<bool>,
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    enum Shape {
+        Empty,
+        Circle(i32),
+        Rect { w: i32, h: i32 },
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let config = Config {
+            name: "rusil".to_string(),
+            port: 8080,
+            tags: vec!["a".to_string()],
+            debug: None,
+        };
+        let e = to_expr(&config).unwrap();
+        assert_eq!(e.to_string(), "(Config.new rusil 8080 [a] option.none)");
+        assert_eq!(from_expr::<Config>(e).unwrap(), config);
+
+        let shapes = vec![Shape::Empty, Shape::Circle(2), Shape::Rect { w: 2, h: 3 }];
+        let e = to_expr(&shapes).unwrap();
+        assert_eq!(
+            e.to_string(),
+            "[Shape.Empty, (Shape.Circle 2), (Shape.Rect 2 3)]"
+        );
+        assert_eq!(from_expr::<Vec<Shape>>(e).unwrap(), shapes);
+
+        let mut map = BTreeMap::new();
+        map.insert("a".to_string(), 1);
+        map.insert("b".to_string(), 2);
+        let e = to_expr(&map).unwrap();
+        assert_eq!(e.to_string(), "[[a, 1], [b, 2]]");
+        assert_eq!(from_expr::<BTreeMap<String, i32>>(e).unwrap(), map);
+    }
+
+    #[test]
+    fn test_with_interpreter() {
+        let mut interpreter = Interpreter::new();
+        let config = Config {
+            name: "rusil".to_string(),
+            port: 8080,
+            tags: vec![],
+            debug: Some(true),
+        };
+        interpreter.set_var("config", to_expr(&config).unwrap());
+        let result = interpreter
+            .eval_str(
+                "((enum Shape Empty (Circle r) (Rect w h)) \
+                  (match config ((Config.new name port tags (option.some true)) (Shape.Rect port 2)) (_ Shape.Empty)))",
+            )
+            .unwrap();
+        assert_eq!(
+            from_expr::<Shape>(result).unwrap(),
+            Shape::Rect { w: 8080, h: 2 }
+        );
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Point {
+        x: i32,
+        y: i32,
+    }
+
+    #[test]
+    fn test_field_names() {
+        let mut interpreter = Interpreter::new();
+        interpreter
+            .eval_str("((enum Point (new y x)) (enum Shape Empty (Circle r) (Rect h w)))")
+            .unwrap();
+        let point = Point { x: 1, y: 2 };
+        let e = interpreter.to_expr(&point).unwrap();
+        assert_eq!(e.to_string(), "(Point.new 2 1)");
+        assert_eq!(interpreter.from_expr::<Point>(e).unwrap(), point);
+        // Without the declarations, the fields are matched by position
+        assert_eq!(to_expr(&point).unwrap().to_string(), "(Point.new 1 2)");
+
+        let result = interpreter.eval_str("(Shape.Rect 3 2)").unwrap();
+        assert_eq!(
+            interpreter.from_expr::<Shape>(result).unwrap(),
+            Shape::Rect { w: 2, h: 3 }
+        );
+
+        interpreter.eval_str("(enum Point (new a b))").unwrap();
+        assert!(interpreter.to_expr(&point).is_err());
+        let result = interpreter.eval_str("(Point.new 1 2)").unwrap();
+        assert!(interpreter.from_expr::<Point>(result).is_err());
+    }
+
+    #[test]
+    fn test_conversion_errors() {
+        assert!(to_expr(&1.5).is_err());
+        assert!(to_expr(&u64::MAX).is_err());
+        assert!(from_expr::<u8>(Expr::Number(-1)).is_err());
+        assert!(from_expr::<String>(Expr::Number(1)).is_err());
+        assert!(from_expr::<Shape>(Expr::Number(1)).is_err());
+        assert!(from_expr::<Config>(Expr::List(vec![Expr::Number(1)])).is_err());
+    }
+}
@@ -0,0 +1,308 @@
+use crate::ast::SourceMap;
+use crate::context::*;
+use crate::enums::*;
+use crate::errors::*;
+use crate::functions::*;
+use crate::interpreter::*;
+use crate::reader::Span;
+use crate::tokens::*;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::rc::Rc;
+
+/// Expression of the program that was never evaluated
+struct Uncovered {
+    line: usize,
+    column: usize,
+    snippet: String,
+}
+
+/// Expressions and functions of a file that were evaluated, and how many times
+pub struct FileCoverage {
+    name: String,
+    /// Number of evaluations of each line: the least evaluated expression starting on it
+    lines: BTreeMap<usize, u64>,
+    /// Outermost expressions that were never evaluated
+    uncovered: Vec<Uncovered>,
+    /// Defined functions, with the line of their definition and the evaluations of their core
+    functions: Vec<(String, usize, u64)>,
+}
+
+/// Evaluations of the expressions, by position in the code, and calls of the functions, by name
+#[derive(Default)]
+struct Hits {
+    expressions: HashMap<Span, u64>,
+    functions: HashMap<String, u64>,
+}
+
+/// Hook counting the evaluations of each expression read from the code, and the calls
+struct Counter {
+    sources: SourceMap,
+    hits: Rc<RefCell<Hits>>,
+    /// Number of functions being called when each expression being evaluated started
+    calls: Vec<usize>,
+}
+
+impl Hook for Counter {
+    fn before(
+        &mut self,
+        e: &Expr,
+        _variables: &mut HashMap<String, Expr>,
+        _functions: &mut HashMap<String, Function>,
+        _enums: &mut HashMap<String, Vec<Variant>>,
+        context: &mut Context,
+    ) -> Result<(), LangError> {
+        let mut hits = self.hits.borrow_mut();
+        if let Some(span) = self.sources.enter(e) {
+            *hits.expressions.entry(span).or_default() += 1;
+        }
+        // A function was called since the evaluation of the enclosing expression started
+        let calls = context.get_calls();
+        if calls.len() > self.calls.last().copied().unwrap_or(0) {
+            if let Some(name) = calls.last() {
+                *hits.functions.entry(name.clone()).or_default() += 1;
+            }
+        }
+        self.calls.push(calls.len());
+        Ok(())
+    }
+
+    fn after(&mut self, _e: &Expr, _result: &Result<Expr, LangError>, _context: &mut Context) {
+        self.sources.leave();
+        self.calls.pop();
+    }
+}
+
+/// Returns the sub-expressions evaluated by the expression, with their indexes in
+/// `Expr::children`, leaving out the names it defines or refers to, like the name of
+/// a function in `(call NAME ...)`
+fn evaluated_children(e: &Expr) -> Vec<(usize, &Expr)> {
+    let children = e.children().into_iter().enumerate();
+    match e {
+        Expr::Let(..) | Expr::Set(..) | Expr::Define(..) | Expr::For(..) | Expr::Call(..) => {
+            children.skip(1).collect()
+        }
+        Expr::Enum(..) | Expr::Variants(..) => vec![],
+        _ => children.collect(),
+    }
+}
+
+impl FileCoverage {
+    fn new(name: &str) -> Self {
+        FileCoverage {
+            name: name.to_string(),
+            lines: BTreeMap::new(),
+            uncovered: Vec::new(),
+            functions: Vec::new(),
+        }
+    }
+
+    /// Records the evaluations of the expression at the position, and of its sub-expressions
+    /// `parent_covered` is false if a containing expression was never evaluated
+    fn record(
+        &mut self,
+        e: &Expr,
+        position: Option<Span>,
+        sources: &SourceMap,
+        hits: &Hits,
+        parent_covered: bool,
+    ) {
+        let count = position
+            .and_then(|s| hits.expressions.get(&s).copied())
+            .unwrap_or(0);
+        if let Some(position) = position {
+            let (line, column) = sources.position(position);
+            let line_hits = self.lines.entry(line).or_insert(count);
+            *line_hits = (*line_hits).min(count);
+            if count == 0 && parent_covered {
+                self.uncovered.push(Uncovered {
+                    line,
+                    column,
+                    snippet: sources.snippet(e, Some(position)),
+                });
+            }
+        }
+        if let Expr::Define(name, ..) = e {
+            let name = name.get_var(String::new()).unwrap_or_default();
+            let line = position.map_or(0, |s| sources.line(s));
+            let calls = hits.functions.get(&name).copied().unwrap_or(0);
+            self.functions.push((name, line, calls));
+        }
+        let source = e.get_source();
+        for (i, child) in evaluated_children(e) {
+            let position = child
+                .get_span()
+                .or_else(|| source.and_then(|s| s.get_child_span(i)));
+            self.record(child, position, sources, hits, count > 0);
+        }
+    }
+
+    /// Returns the number of covered lines, and the number of lines with an expression
+    fn covered_lines(&self) -> (usize, usize) {
+        let covered = self.lines.values().filter(|hits| **hits > 0).count();
+        (covered, self.lines.len())
+    }
+
+    /// Returns the lines that were never evaluated, like `4, 7-9`
+    fn uncovered_lines(&self) -> String {
+        let mut ranges: Vec<(usize, usize)> = Vec::new();
+        for (line, _) in self.lines.iter().filter(|(_, hits)| **hits == 0) {
+            match ranges.last_mut() {
+                Some((_, end)) if *end + 1 == *line => *end = *line,
+                _ => ranges.push((*line, *line)),
+            }
+        }
+        ranges
+            .iter()
+            .map(|(start, end)| match start == end {
+                true => start.to_string(),
+                false => format!("{}-{}", start, end),
+            })
+            .collect::<Vec<String>>()
+            .join(", ")
+    }
+}
+
+/// Executes the program read from the file `name`, and returns its result and its coverage
+pub fn cover(name: &str, code: &str, context: Context) -> (Result<Expr, LangError>, FileCoverage) {
+    let mut coverage = FileCoverage::new(name);
+    let tree = match Expr::token_tree(code) {
+        Ok(tree) => tree,
+        Err(e) => return (Err(e), coverage),
+    };
+    let sources = SourceMap::new(code);
+    let hits = Rc::new(RefCell::new(Hits::default()));
+    let mut interpreter = Interpreter::with_context(context);
+    interpreter.get_context().add_hook(Box::new(Counter {
+        sources: SourceMap::new(code),
+        hits: hits.clone(),
+        calls: Vec::new(),
+    }));
+    let result = interpreter.eval(&tree);
+    coverage.record(&tree, tree.get_span(), &sources, &hits.borrow(), true);
+    (result, coverage)
+}
+
+/// Returns the percentage of covered lines
+fn percent(covered: usize, total: usize) -> f64 {
+    match total {
+        0 => 100.0,
+        _ => 100.0 * covered as f64 / total as f64,
+    }
+}
+
+/// Returns the text report of the files: the covered lines of each one, its uncovered lines,
+/// and the outermost expressions that were never evaluated
+pub fn report(files: &[FileCoverage]) -> String {
+    let mut out = String::new();
+    let (mut covered, mut total) = (0, 0);
+    for file in files {
+        let (file_covered, file_total) = file.covered_lines();
+        covered += file_covered;
+        total += file_total;
+        out.push_str(&format!(
+            "{}: {}/{} lines covered ({:.1}%)\n",
+            file.name,
+            file_covered,
+            file_total,
+            percent(file_covered, file_total)
+        ));
+        if file_covered < file_total {
+            out.push_str(&format!(
+                "    uncovered lines: {}\n",
+                file.uncovered_lines()
+            ));
+        }
+        for u in file.uncovered.iter() {
+            out.push_str(&format!("    {}:{}  {}\n", u.line, u.column, u.snippet));
+        }
+    }
+    out.push_str(&format!(
+        "total: {}/{} lines covered ({:.1}%)\n",
+        covered,
+        total,
+        percent(covered, total)
+    ));
+    out
+}
+
+/// Returns the coverage of the files in the lcov format, read by coverage tools
+pub fn lcov(files: &[FileCoverage]) -> String {
+    let mut out = String::new();
+    for file in files {
+        out.push_str(&format!("TN:\nSF:{}\n", file.name));
+        for (name, line, _) in file.functions.iter() {
+            out.push_str(&format!("FN:{},{}\n", line, name));
+        }
+        for (name, _, hits) in file.functions.iter() {
+            out.push_str(&format!("FNDA:{},{}\n", hits, name));
+        }
+        let called = file.functions.iter().filter(|(_, _, hits)| *hits > 0);
+        out.push_str(&format!(
+            "FNF:{}\nFNH:{}\n",
+            file.functions.len(),
+            called.count()
+        ));
+        for (line, hits) in file.lines.iter() {
+            out.push_str(&format!("DA:{},{}\n", line, hits));
+        }
+        let (covered, total) = file.covered_lines();
+        out.push_str(&format!("LF:{}\nLH:{}\nend_of_record\n", total, covered));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests_coverage {
+    use crate::coverage::*;
+    use std::io::Cursor;
+
+    const CODE: &str = "(def fact n
+  (if (<= n 1)
+    1
+    (* n (call fact (- n 1)))))
+(def unused x
+  (+ x 1))
+(call fact 1)";
+
+    fn cover_code(code: &str) -> (Result<Expr, LangError>, FileCoverage) {
+        let context = Context::new(vec![], Box::new(Cursor::new("")), Box::new(Vec::new()));
+        cover("fact.rsl", code, context)
+    }
+
+    #[test]
+    fn test_report() {
+        let (result, coverage) = cover_code(CODE);
+        assert_eq!(result.unwrap(), Expr::Number(1));
+        assert_eq!(
+            report(&[coverage]),
+            "fact.rsl: 5/7 lines covered (71.4%)
+    uncovered lines: 4, 6
+    4:5  (* n (call fact (- n 1)))
+    6:3  (+ x 1)
+total: 5/7 lines covered (71.4%)
+"
+        );
+    }
+
+    #[test]
+    fn test_lcov() {
+        let (_, coverage) = cover_code(CODE);
+        let lcov = lcov(&[coverage]);
+        assert!(lcov.starts_with("TN:\nSF:fact.rsl\nFN:1,fact\nFN:5,unused\n"));
+        assert!(lcov.contains("FNDA:1,fact\nFNDA:0,unused\nFNF:2\nFNH:1\n"));
+        assert!(lcov.contains("DA:3,1\nDA:4,0\nDA:5,1\nDA:6,0\nDA:7,1\n"));
+        assert!(lcov.ends_with("LF:7\nLH:5\nend_of_record\n"));
+    }
+
+    #[test]
+    fn test_same_line() {
+        // The branch that was not taken makes the line uncovered
+        let (_, coverage) = cover_code("(if true 1 (call f))");
+        assert_eq!(coverage.lines.get(&1), Some(&0));
+        assert_eq!(coverage.uncovered.len(), 1);
+        let (result, coverage) = cover_code("(+ 1");
+        assert!(result.is_err());
+        assert!(coverage.lines.is_empty());
+    }
+}
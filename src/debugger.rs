@@ -0,0 +1,285 @@
+use crate::ast::SourceMap;
+use crate::context::*;
+use crate::enums::*;
+use crate::errors::*;
+use crate::functions::*;
+use crate::interpreter::*;
+use crate::reader::Span;
+use crate::tokens::*;
+use std::collections::HashMap;
+
+/// Text printed by the `help` command of the debugger
+const HELP: &str = "Commands:
+    s, step               Stop at the next expression
+    n, next               Stop after the current expression
+    f, finish             Stop when the current function returns
+    c, continue           Run until a breakpoint
+    b, break LINE|NAME    Stop at the line, or at each call of the function
+    d, delete             Remove all the breakpoints
+    p, print EXPR         Evaluate the expression in the current scope
+    e, env                Print the variables of the current scope
+    bt, backtrace         Print the functions being called
+    q, quit               Stop the program
+    h, help               Print this help
+An empty line repeats the previous command
+";
+
+/// Where the debugger stops next, besides the breakpoints
+enum Mode {
+    Step,
+    /// Stop at an expression at most as deeply nested as the given depth
+    Next(usize),
+    /// Stop when fewer functions than the given number are being called
+    Finish(usize),
+    Continue,
+}
+
+enum Breakpoint {
+    Line(usize),
+    Function(String),
+}
+
+/// Hook stopping the program to read commands
+struct Debugger {
+    source_name: String,
+    sources: SourceMap,
+    breakpoints: Vec<Breakpoint>,
+    mode: Mode,
+    /// Lines of the expressions being evaluated, the innermost last
+    lines: Vec<Option<usize>>,
+    previous_command: String,
+}
+
+fn io_error(e: std::io::Error) -> LangError {
+    LangError::new_io_error(e.to_string(), "debug".to_string())
+}
+
+impl Debugger {
+    /// Returns true if a breakpoint is on the expression
+    fn at_breakpoint(&self, e: &Expr, line: Option<usize>, parent: Option<usize>) -> bool {
+        self.breakpoints.iter().any(|b| match b {
+            // Stop only at the outermost expression of the line
+            Breakpoint::Line(l) => line == Some(*l) && parent != Some(*l),
+            Breakpoint::Function(name) => {
+                matches!(e, Expr::Call(f, _, _) if **f == Expr::Var(name.to_string()))
+            }
+        })
+    }
+
+    /// Reads and executes commands, until one resumes the program
+    fn prompt(
+        &mut self,
+        e: &Expr,
+        span: Option<Span>,
+        variables: &mut HashMap<String, Expr>,
+        functions: &mut HashMap<String, Function>,
+        enums: &mut HashMap<String, Vec<Variant>>,
+        context: &mut Context,
+    ) -> Result<(), LangError> {
+        let position = span.map_or("?".to_string(), |s| self.sources.line(s).to_string());
+        let stop = format!(
+            "{}:{}  {}\n",
+            self.source_name,
+            position,
+            self.sources.snippet(e, span)
+        );
+        context
+            .get_output()
+            .write_all(stop.as_bytes())
+            .map_err(io_error)?;
+        loop {
+            let output = context.get_output();
+            output.write_all(b"(debug) ").map_err(io_error)?;
+            output.flush().map_err(io_error)?;
+            let mut command = String::new();
+            if context
+                .get_input()
+                .read_line(&mut command)
+                .map_err(io_error)?
+                == 0
+            {
+                // Without commands, the program runs until its end
+                self.breakpoints.clear();
+                self.mode = Mode::Continue;
+                return Ok(());
+            }
+            let command = match command.trim() {
+                "" => self.previous_command.clone(),
+                command => command.to_string(),
+            };
+            self.previous_command = command.clone();
+            let (name, argument) = command.split_once(' ').unwrap_or((&command, ""));
+            let argument = argument.trim();
+            let answer = match name {
+                "s" | "step" => {
+                    self.mode = Mode::Step;
+                    return Ok(());
+                }
+                "n" | "next" => {
+                    self.mode = Mode::Next(self.lines.len() - 1);
+                    return Ok(());
+                }
+                "f" | "finish" => {
+                    self.mode = Mode::Finish(context.get_calls().len());
+                    return Ok(());
+                }
+                "c" | "continue" => {
+                    self.mode = Mode::Continue;
+                    return Ok(());
+                }
+                "q" | "quit" => return Err(LangError::new_exit(0)),
+                "b" | "break" if argument.is_empty() => "Expected a line or a function".to_string(),
+                "b" | "break" => {
+                    let breakpoint = match argument.parse() {
+                        Ok(line) => Breakpoint::Line(line),
+                        Err(_) => Breakpoint::Function(argument.to_string()),
+                    };
+                    self.breakpoints.push(breakpoint);
+                    format!("Breakpoint {} at {}", self.breakpoints.len(), argument)
+                }
+                "d" | "delete" => {
+                    self.breakpoints.clear();
+                    "Breakpoints removed".to_string()
+                }
+                "p" | "print" => match Expr::token_tree(argument)
+                    .and_then(|x| x.evaluate(variables, functions, enums, context))
+                {
+                    Ok(value) => value.to_string(),
+                    Err(e) => e.to_string(),
+                },
+                "e" | "env" => {
+                    let mut names: Vec<&String> = variables.keys().collect();
+                    names.sort();
+                    names
+                        .iter()
+                        .map(|name| format!("{} = {}", name, variables[*name]))
+                        .collect::<Vec<String>>()
+                        .join("\n")
+                }
+                "bt" | "backtrace" => context
+                    .get_calls()
+                    .iter()
+                    .rev()
+                    .chain(std::iter::once(&"<program>".to_string()))
+                    .enumerate()
+                    .map(|(i, name)| format!("#{} {}", i, name))
+                    .collect::<Vec<String>>()
+                    .join("\n"),
+                "h" | "help" => HELP.trim_end().to_string(),
+                _ => format!("Unknown command \"{}\", type h for help", name),
+            };
+            writeln!(context.get_output(), "{}", answer).map_err(io_error)?;
+        }
+    }
+}
+
+impl Hook for Debugger {
+    fn before(
+        &mut self,
+        e: &Expr,
+        variables: &mut HashMap<String, Expr>,
+        functions: &mut HashMap<String, Function>,
+        enums: &mut HashMap<String, Vec<Variant>>,
+        context: &mut Context,
+    ) -> Result<(), LangError> {
+        let parent = self.lines.last().copied().flatten();
+        let span = self.sources.enter(e);
+        let line = span.map(|span| self.sources.line(span));
+        let depth = self.lines.len();
+        self.lines.push(line);
+        let stop = match self.mode {
+            Mode::Step => true,
+            Mode::Next(d) => depth <= d,
+            Mode::Finish(calls) => context.get_calls().len() < calls,
+            Mode::Continue => false,
+        };
+        if stop || self.at_breakpoint(e, line, parent) {
+            self.prompt(e, span, variables, functions, enums, context)?;
+        }
+        Ok(())
+    }
+
+    fn after(&mut self, _e: &Expr, _result: &Result<Expr, LangError>, _context: &mut Context) {
+        self.sources.leave();
+        self.lines.pop();
+    }
+}
+
+/// Executes the program, reading the commands of the debugger from the input of the context
+/// It stops before its first expression
+pub fn debug(source_name: &str, code: &str, context: Context) -> Result<Expr, LangError> {
+    let tree = Expr::token_tree(code)?;
+    let debugger = Debugger {
+        source_name: source_name.to_string(),
+        sources: SourceMap::new(code),
+        breakpoints: Vec::new(),
+        mode: Mode::Step,
+        lines: Vec::new(),
+        previous_command: String::new(),
+    };
+    let mut interpreter = Interpreter::with_context(context);
+    interpreter.get_context().add_hook(Box::new(debugger));
+    interpreter.eval(&tree)
+}
+
+#[cfg(test)]
+mod tests_debugger {
+    use crate::debugger::*;
+    use std::io::Cursor;
+
+    const CODE: &str = "(def fact n
+  (if (<= n 1)
+    1
+    (* n (call fact (- n 1)))))
+(let r (call fact 3))
+r";
+
+    /// Debugs the program with the commands, and returns its result and the output
+    fn debug_with(commands: &str) -> (Result<Expr, LangError>, String) {
+        let output = SharedOutput::default();
+        let context = Context::new(
+            vec![],
+            Box::new(Cursor::new(commands.to_string())),
+            Box::new(output.clone()),
+        );
+        (debug("fact.rsl", CODE, context), output.contents())
+    }
+
+    #[test]
+    fn test_step_and_next() {
+        let (result, output) = debug_with("s\n\nn\nn\nc\n");
+        assert_eq!(result.unwrap(), Expr::Number(6));
+        let stops: Vec<&str> = output
+            .lines()
+            .map(|l| l.trim_start_matches("(debug) "))
+            .filter(|l| l.starts_with("fact.rsl"))
+            .collect();
+        assert_eq!(
+            stops,
+            vec![
+                "fact.rsl:1  (def fact n (if (<= n 1) 1 (* n (call fact (- n 1))))) (l...",
+                "fact.rsl:1  (def fact n (if (<= n 1) 1 (* n (call fact (- n 1)))))",
+                "fact.rsl:5  (let r (call fact 3))",
+                "fact.rsl:6  r",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_breakpoints() {
+        let (result, output) = debug_with("b fact\nc\nc\ne\nbt\nd\nb 3\nc\np n\nq\n");
+        assert!(matches!(result, Err(LangError::Exit(_))));
+        assert!(output.contains("fact.rsl:5  (call fact 3)"));
+        assert!(output.contains("fact.rsl:4  (call fact (- n 1))\n(debug) n = 3\n"));
+        assert!(output.contains("#0 fact\n#1 <program>"));
+        assert!(output.contains("fact.rsl:3  1\n(debug) 1\n"));
+    }
+
+    #[test]
+    fn test_finish_and_end_of_input() {
+        let (result, output) = debug_with("b fact\nc\nc\nd\nf\np (+ r 1)\nfoo\n");
+        assert_eq!(result.unwrap(), Expr::Number(6));
+        assert!(output.contains("fact.rsl:6  r\n(debug) 7\n"));
+        assert!(output.contains("Unknown command \"foo\""));
+    }
+}
@@ -0,0 +1,58 @@
+use crate::errors::*;
+use crate::reader::*;
+use std::collections::HashMap;
+
+/// Item of an enum, with the names of the fields it carries
+#[derive(Debug, PartialEq, Clone)]
+pub struct Variant {
+    name: String,
+    fields: Vec<String>,
+}
+
+impl Variant {
+    pub(crate) fn new(name: String, fields: Vec<String>) -> Self {
+        Variant { name, fields }
+    }
+
+    /// Returns the variant associated with `ITEM` or `(ITEM FIELD1 ... FIELDn)`
+    pub(crate) fn token_tree(e: &Spanned) -> Result<Self, LangError> {
+        match e.atom() {
+            Some(name) => Ok(Variant::new(name.to_string(), vec![])),
+            None => match e.get_items().split_first() {
+                Some((name, fields)) => Ok(Variant::new(
+                    name.get_atom("an item name")?,
+                    fields
+                        .iter()
+                        .map(|f| f.get_atom("a field name"))
+                        .collect::<Result<Vec<String>, LangError>>()?,
+                )),
+                None => Err(LangError::new_syntax_error(
+                    "Expected an item name".to_string(),
+                    e.to_string(),
+                )),
+            },
+        }
+    }
+
+    pub(crate) fn get_name(&self) -> String {
+        self.name.clone()
+    }
+
+    pub(crate) fn get_fields(&self) -> Vec<String> {
+        self.fields.clone()
+    }
+}
+
+/// Returns the enums defined before the program starts:
+/// `option`, with the items `none` and `(some value)`, returned by `(env NAME)`
+pub(crate) fn builtin_enums() -> HashMap<String, Vec<Variant>> {
+    let mut enums = HashMap::new();
+    enums.insert(
+        "option".to_string(),
+        vec![
+            Variant::new("none".to_string(), vec![]),
+            Variant::new("some".to_string(), vec!["value".to_string()]),
+        ],
+    );
+    enums
+}
@@ -0,0 +1,55 @@
+use crate::reader::Span;
+use std::error::Error;
+use std::fmt;
+
+pub struct ArityError {
+    message: String,
+    /// Position of the faulty expression in the code, if it was read from it
+    span: Option<Span>,
+}
+
+impl ArityError {
+    pub fn new(
+        name: String,
+        expected_min: usize,
+        expected_max: Option<usize>,
+        given: usize,
+        faulty_expression: String,
+    ) -> Self {
+        let expected = match expected_max {
+            Some(max) if max == expected_min => format!("{}", max),
+            Some(max) => format!("{} to {}", expected_min, max),
+            None => format!("at least {}", expected_min),
+        };
+        let message = format!(
+            "\n>>> ArityError:\n\n\t\x1B[31m{}\x1B[39m\n\n\t\"{}\" expects {} argument(s)\n\tGiven: {}\n",
+            faulty_expression, name, expected, given
+        );
+        ArityError {
+            message,
+            span: None,
+        }
+    }
+
+    pub fn get_span(&self) -> Option<Span> {
+        self.span
+    }
+
+    pub fn set_span(&mut self, span: Span) {
+        self.span = Some(span);
+    }
+}
+
+impl fmt::Display for ArityError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}", self.message)
+    }
+}
+
+impl fmt::Debug for ArityError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}", self.message)
+    }
+}
+
+impl Error for ArityError {}
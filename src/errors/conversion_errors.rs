@@ -0,0 +1,28 @@
+use std::error::Error;
+use std::fmt;
+
+/// Raised when a Rust value and a Rusil value cannot be converted into each other
+pub struct ConversionError {
+    message: String,
+}
+
+impl ConversionError {
+    pub fn new(message: String) -> Self {
+        let message = format!("\n>>> ConversionError:\n\n\t{}\n", message);
+        ConversionError { message }
+    }
+}
+
+impl fmt::Display for ConversionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}", self.message)
+    }
+}
+
+impl fmt::Debug for ConversionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}", self.message)
+    }
+}
+
+impl Error for ConversionError {}
@@ -0,0 +1,33 @@
+use std::error::Error;
+use std::fmt;
+
+/// Raised by `(exit CODE)`: not an error, it stops the program with the given status
+pub struct Exit {
+    code: i32,
+    message: String,
+}
+
+impl Exit {
+    pub fn new(code: i32) -> Self {
+        let message = format!("Exited with status {}", code);
+        Exit { code, message }
+    }
+
+    pub fn get_code(&self) -> i32 {
+        self.code
+    }
+}
+
+impl fmt::Display for Exit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}", self.message)
+    }
+}
+
+impl fmt::Debug for Exit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}", self.message)
+    }
+}
+
+impl Error for Exit {}
@@ -0,0 +1,44 @@
+use crate::reader::Span;
+use std::error::Error;
+use std::fmt;
+
+pub struct IOError {
+    message: String,
+    /// Position of the faulty expression in the code, if it was read from it
+    span: Option<Span>,
+}
+
+impl IOError {
+    pub fn new(message: String, faulty_expression: String) -> Self {
+        let message = format!(
+            "\n>>> IOError:\n\n\t\x1B[31m{}\x1B[39m\n\n\t{}\n",
+            faulty_expression, message
+        );
+        IOError {
+            message,
+            span: None,
+        }
+    }
+
+    pub fn get_span(&self) -> Option<Span> {
+        self.span
+    }
+
+    pub fn set_span(&mut self, span: Span) {
+        self.span = Some(span);
+    }
+}
+
+impl fmt::Display for IOError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}", self.message)
+    }
+}
+
+impl fmt::Debug for IOError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}", self.message)
+    }
+}
+
+impl Error for IOError {}
@@ -0,0 +1,44 @@
+use crate::reader::Span;
+use std::error::Error;
+use std::fmt;
+
+pub struct MatchError {
+    message: String,
+    /// Position of the faulty expression in the code, if it was read from it
+    span: Option<Span>,
+}
+
+impl MatchError {
+    pub fn new(value: String, faulty_expression: String) -> Self {
+        let message = format!(
+            "\n>>> MatchError:\n\n\t\x1B[31m{}\x1B[39m\n\n\tNo pattern matches the value \"{}\"\n",
+            faulty_expression, value
+        );
+        MatchError {
+            message,
+            span: None,
+        }
+    }
+
+    pub fn get_span(&self) -> Option<Span> {
+        self.span
+    }
+
+    pub fn set_span(&mut self, span: Span) {
+        self.span = Some(span);
+    }
+}
+
+impl fmt::Display for MatchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}", self.message)
+    }
+}
+
+impl fmt::Debug for MatchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}", self.message)
+    }
+}
+
+impl Error for MatchError {}
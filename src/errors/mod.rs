@@ -1,16 +1,45 @@
-pub(crate) mod type_errors;
-pub(crate) mod undefined_variable_errors;
+pub mod arithmetic_errors;
+pub mod arity_errors;
+pub mod conversion_errors;
+pub mod exit_errors;
+pub mod io_errors;
+pub mod match_errors;
+pub mod permission_errors;
+pub mod resource_errors;
+pub mod syntax_errors;
+pub mod type_errors;
+pub mod undefined_variable_errors;
 
+use crate::capabilities::Capability;
+use crate::errors::arithmetic_errors::ArithmeticError;
+use crate::errors::arity_errors::ArityError;
+use crate::errors::conversion_errors::ConversionError;
+use crate::errors::exit_errors::Exit;
+use crate::errors::io_errors::IOError;
+use crate::errors::match_errors::MatchError;
+use crate::errors::permission_errors::PermissionDenied;
+use crate::errors::resource_errors::ResourceExhausted;
+use crate::errors::syntax_errors::SyntaxError;
 use crate::errors::type_errors::TypeError;
 use crate::errors::undefined_variable_errors::UndefVarError;
+use crate::reader::Span;
 use crate::types::Type;
-use std;
 use std::error::Error;
 use std::fmt;
 
-pub(crate) enum LangError {
+#[allow(clippy::enum_variant_names)]
+pub enum LangError {
     TypeError(TypeError),
     UndefVarError(UndefVarError),
+    MatchError(MatchError),
+    ArityError(ArityError),
+    SyntaxError(SyntaxError),
+    ArithmeticError(ArithmeticError),
+    IOError(IOError),
+    ConversionError(ConversionError),
+    ResourceExhausted(ResourceExhausted),
+    PermissionDenied(PermissionDenied),
+    Exit(Exit),
 }
 
 impl LangError {
@@ -24,6 +53,120 @@ impl LangError {
     pub fn new_undefined_variable_error(var_name: String, faulty_expression: String) -> Self {
         LangError::UndefVarError(UndefVarError::new(var_name, faulty_expression))
     }
+    pub fn new_match_error(value: String, faulty_expression: String) -> Self {
+        LangError::MatchError(MatchError::new(value, faulty_expression))
+    }
+    pub fn new_arity_error(
+        name: String,
+        expected_min: usize,
+        expected_max: Option<usize>,
+        given: usize,
+        faulty_expression: String,
+    ) -> Self {
+        LangError::ArityError(ArityError::new(
+            name,
+            expected_min,
+            expected_max,
+            given,
+            faulty_expression,
+        ))
+    }
+    pub fn new_syntax_error(message: String, faulty_expression: String) -> Self {
+        LangError::SyntaxError(SyntaxError::new(message, faulty_expression))
+    }
+    pub fn new_arithmetic_error(message: String, faulty_expression: String) -> Self {
+        LangError::ArithmeticError(ArithmeticError::new(message, faulty_expression))
+    }
+    pub fn new_io_error(message: String, faulty_expression: String) -> Self {
+        LangError::IOError(IOError::new(message, faulty_expression))
+    }
+    pub fn new_conversion_error(message: String) -> Self {
+        LangError::ConversionError(ConversionError::new(message))
+    }
+    pub fn new_resource_exhausted(resource: String, message: String) -> Self {
+        LangError::ResourceExhausted(ResourceExhausted::new(resource, message))
+    }
+    pub fn new_permission_denied(capability: Capability, faulty_expression: String) -> Self {
+        LangError::PermissionDenied(PermissionDenied::new(capability, faulty_expression))
+    }
+    pub fn new_exit(code: i32) -> Self {
+        LangError::Exit(Exit::new(code))
+    }
+
+    /// Returns the position of the faulty expression in the code, if it is known
+    pub fn get_span(&self) -> Option<Span> {
+        match self {
+            LangError::TypeError(e) => e.get_span(),
+            LangError::UndefVarError(e) => e.get_span(),
+            LangError::MatchError(e) => e.get_span(),
+            LangError::ArityError(e) => e.get_span(),
+            LangError::SyntaxError(e) => e.get_span(),
+            LangError::ArithmeticError(e) => e.get_span(),
+            LangError::IOError(e) => e.get_span(),
+            LangError::PermissionDenied(e) => e.get_span(),
+            LangError::ConversionError(_)
+            | LangError::ResourceExhausted(_)
+            | LangError::Exit(_) => None,
+        }
+    }
+
+    /// Returns the error positioned at the span, unless a position was already known:
+    /// the innermost expression raising it is the faulty one
+    pub(crate) fn with_span(mut self, span: Option<Span>) -> Self {
+        let span = match span {
+            Some(span) if self.get_span().is_none() => span,
+            _ => return self,
+        };
+        match &mut self {
+            LangError::TypeError(e) => e.set_span(span),
+            LangError::UndefVarError(e) => e.set_span(span),
+            LangError::MatchError(e) => e.set_span(span),
+            LangError::ArityError(e) => e.set_span(span),
+            LangError::SyntaxError(e) => e.set_span(span),
+            LangError::ArithmeticError(e) => e.set_span(span),
+            LangError::IOError(e) => e.set_span(span),
+            LangError::PermissionDenied(e) => e.set_span(span),
+            LangError::ConversionError(_)
+            | LangError::ResourceExhausted(_)
+            | LangError::Exit(_) => {}
+        }
+        self
+    }
+
+    /// Returns the name of the kind of error, like `TypeError`
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            LangError::TypeError(_) => "TypeError",
+            LangError::UndefVarError(_) => "UndefVarError",
+            LangError::MatchError(_) => "MatchError",
+            LangError::ArityError(_) => "ArityError",
+            LangError::SyntaxError(_) => "SyntaxError",
+            LangError::ArithmeticError(_) => "ArithmeticError",
+            LangError::IOError(_) => "IOError",
+            LangError::ConversionError(_) => "ConversionError",
+            LangError::ResourceExhausted(_) => "ResourceExhausted",
+            LangError::PermissionDenied(_) => "PermissionDenied",
+            LangError::Exit(_) => "Exit",
+        }
+    }
+
+    /// Returns the status with which the process exits because of the error
+    /// A program can also exit with any status from 0 to 255, those of the errors included
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            LangError::SyntaxError(_) => 2,
+            LangError::TypeError(_) => 3,
+            LangError::UndefVarError(_) => 4,
+            LangError::ArityError(_) => 5,
+            LangError::MatchError(_) => 6,
+            LangError::ArithmeticError(_) => 7,
+            LangError::IOError(_) => 8,
+            LangError::ConversionError(_) => 9,
+            LangError::ResourceExhausted(_) => 10,
+            LangError::PermissionDenied(_) => 11,
+            LangError::Exit(e) => e.get_code(),
+        }
+    }
 }
 
 impl fmt::Display for LangError {
@@ -31,7 +174,15 @@ impl fmt::Display for LangError {
         match self {
             LangError::TypeError(e) => e.fmt(f),
             LangError::UndefVarError(e) => e.fmt(f),
-            _ => unimplemented!(),
+            LangError::MatchError(e) => e.fmt(f),
+            LangError::ArityError(e) => e.fmt(f),
+            LangError::SyntaxError(e) => e.fmt(f),
+            LangError::ArithmeticError(e) => e.fmt(f),
+            LangError::IOError(e) => e.fmt(f),
+            LangError::ConversionError(e) => e.fmt(f),
+            LangError::ResourceExhausted(e) => e.fmt(f),
+            LangError::PermissionDenied(e) => e.fmt(f),
+            LangError::Exit(e) => e.fmt(f),
         }
     }
 }
@@ -41,17 +192,17 @@ impl fmt::Debug for LangError {
         match self {
             LangError::TypeError(e) => e.fmt(f),
             LangError::UndefVarError(e) => e.fmt(f),
-            _ => unimplemented!(),
+            LangError::MatchError(e) => e.fmt(f),
+            LangError::ArityError(e) => e.fmt(f),
+            LangError::SyntaxError(e) => e.fmt(f),
+            LangError::ArithmeticError(e) => e.fmt(f),
+            LangError::IOError(e) => e.fmt(f),
+            LangError::ConversionError(e) => e.fmt(f),
+            LangError::ResourceExhausted(e) => e.fmt(f),
+            LangError::PermissionDenied(e) => e.fmt(f),
+            LangError::Exit(e) => e.fmt(f),
         }
     }
 }
 
-impl Error for LangError {
-    fn description(&self) -> &str {
-        match self {
-            LangError::TypeError(e) => e.description(),
-            LangError::UndefVarError(e) => e.description(),
-            _ => unimplemented!(),
-        }
-    }
-}
+impl Error for LangError {}
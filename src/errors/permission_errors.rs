@@ -0,0 +1,52 @@
+use crate::capabilities::Capability;
+use crate::reader::Span;
+use std::error::Error;
+use std::fmt;
+
+/// Raised when the program uses a capability denied by the interpreter
+pub struct PermissionDenied {
+    capability: Capability,
+    message: String,
+    /// Position of the faulty expression in the code, if it was read from it
+    span: Option<Span>,
+}
+
+impl PermissionDenied {
+    pub fn new(capability: Capability, faulty_expression: String) -> Self {
+        let message = format!(
+            "\n>>> PermissionDenied:\n\n\t\x1B[31m{}\x1B[39m\n\n\tThe capability \"{}\" is not allowed\n",
+            faulty_expression, capability
+        );
+        PermissionDenied {
+            capability,
+            message,
+            span: None,
+        }
+    }
+
+    pub fn get_capability(&self) -> Capability {
+        self.capability
+    }
+
+    pub fn get_span(&self) -> Option<Span> {
+        self.span
+    }
+
+    pub fn set_span(&mut self, span: Span) {
+        self.span = Some(span);
+    }
+}
+
+impl fmt::Display for PermissionDenied {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}", self.message)
+    }
+}
+
+impl fmt::Debug for PermissionDenied {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}", self.message)
+    }
+}
+
+impl Error for PermissionDenied {}
@@ -0,0 +1,31 @@
+use std::error::Error;
+use std::fmt;
+
+/// Raised when the program goes over a limit set by the host, like its step budget or its timeout
+pub struct ResourceExhausted {
+    message: String,
+}
+
+impl ResourceExhausted {
+    pub fn new(resource: String, message: String) -> Self {
+        let message = format!(
+            "\n>>> ResourceExhausted:\n\n\t\x1B[31m{}\x1B[39m\n\n\t{}\n",
+            resource, message
+        );
+        ResourceExhausted { message }
+    }
+}
+
+impl fmt::Display for ResourceExhausted {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}", self.message)
+    }
+}
+
+impl fmt::Debug for ResourceExhausted {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}", self.message)
+    }
+}
+
+impl Error for ResourceExhausted {}
@@ -1,11 +1,12 @@
-use crate::tokens::Expr;
+use crate::reader::Span;
 use crate::types::Type;
-use std;
 use std::error::Error;
 use std::fmt;
 
-pub(crate) struct TypeError {
+pub struct TypeError {
     message: String,
+    /// Position of the faulty expression in the code, if it was read from it
+    span: Option<Span>,
 }
 
 impl TypeError {
@@ -14,7 +15,18 @@ impl TypeError {
             "\n>>> TypeError:\n\n\t\x1B[31m{}\x1B[39m\n\n\tExpected: {:?}\n\tFound: {:?}\n",
             faulty_expression, expected_type, given_type
         );
-        TypeError { message }
+        TypeError {
+            message,
+            span: None,
+        }
+    }
+
+    pub fn get_span(&self) -> Option<Span> {
+        self.span
+    }
+
+    pub fn set_span(&mut self, span: Span) {
+        self.span = Some(span);
     }
 }
 
@@ -30,8 +42,4 @@ impl fmt::Debug for TypeError {
     }
 }
 
-impl Error for TypeError {
-    fn description(&self) -> &str {
-        &self.message
-    }
-}
+impl Error for TypeError {}
@@ -1,11 +1,11 @@
-use crate::tokens::Expr;
-use crate::types::Type;
-use std;
+use crate::reader::Span;
 use std::error::Error;
 use std::fmt;
 
-pub(crate) struct UndefVarError {
+pub struct UndefVarError {
     message: String,
+    /// Position of the faulty expression in the code, if it was read from it
+    span: Option<Span>,
 }
 
 impl UndefVarError {
@@ -14,7 +14,18 @@ impl UndefVarError {
             "\n>>> Undefined Variable:\n\n\t\x1B[31m{}\x1B[39m\n\n\tVariable \"{}\" not found\n",
             faulty_expression, var_name
         );
-        UndefVarError { message }
+        UndefVarError {
+            message,
+            span: None,
+        }
+    }
+
+    pub fn get_span(&self) -> Option<Span> {
+        self.span
+    }
+
+    pub fn set_span(&mut self, span: Span) {
+        self.span = Some(span);
     }
 }
 
@@ -30,8 +41,4 @@ impl fmt::Debug for UndefVarError {
     }
 }
 
-impl Error for UndefVarError {
-    fn description(&self) -> &str {
-        &self.message
-    }
-}
+impl Error for UndefVarError {}
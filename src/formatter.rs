@@ -0,0 +1,281 @@
+use crate::errors::*;
+use crate::reader::*;
+
+/// Maximum width of a formatted line, unless another one is given
+pub const DEFAULT_WIDTH: usize = 80;
+
+/// Element of the source kept by the formatter, comments and empty lines included
+#[derive(Debug, PartialEq)]
+enum Node {
+    Atom(String),
+    List(Vec<Node>),
+    /// List of expressions evaluated one after the other, which has one of them per line
+    Sequence(Vec<Node>),
+    /// `; text`, and whether it ends the line of the previous element
+    Comment(String, bool),
+    /// One or more empty lines between two elements
+    Blank,
+}
+
+/// Builds the elements of the source from the S-expressions and the comments read from it
+struct Builder<'a> {
+    code: &'a str,
+    comments: std::iter::Peekable<std::vec::IntoIter<Span>>,
+}
+
+impl<'a> Builder<'a> {
+    /// Returns the elements of a list, or of the program, from the position `start`:
+    /// its S-expressions, with the comments starting before `end` and the empty lines
+    fn build(&mut self, items: &[Spanned], start: usize, end: usize) -> Vec<Node> {
+        let mut nodes = Vec::new();
+        // End of the previous element, or of the opening parenthesis
+        let mut previous = start;
+        // The elements of a match following its value are arms, not sequences
+        let is_match = items.first().is_some_and(|x| x.is_atom("match"));
+        let mut items = items.iter().enumerate().peekable();
+        loop {
+            let comment = self.comments.next_if(|c| {
+                c.start < end && items.peek().is_none_or(|(_, item)| c.start < item.start)
+            });
+            let (span, node) = match (comment, items.peek()) {
+                (Some(c), _) => (c, None),
+                (None, Some((_, item))) => (item.get_span(), items.next()),
+                (None, None) => return nodes,
+            };
+            // Line breaks since the previous element, or the previous parenthesis
+            let newlines = self.code[previous..span.start].matches('\n').count();
+            if newlines >= 2 && !nodes.is_empty() {
+                nodes.push(Node::Blank);
+            }
+            let trailing = newlines == 0 && !nodes.is_empty();
+            nodes.push(match node {
+                None => Node::Comment(
+                    self.code[span.start..span.end].trim_end().to_string(),
+                    trailing,
+                ),
+                Some((index, item)) => {
+                    let children = item.get_items();
+                    let nodes = self.build(children, span.start + 1, span.end);
+                    match item.atom() {
+                        Some(x) => Node::Atom(x.to_string()),
+                        _ if is_match && index >= 2 => Node::List(nodes),
+                        _ if children.len() >= 2 && children[0].atom().is_none() => {
+                            Node::Sequence(nodes)
+                        }
+                        _ => Node::List(nodes),
+                    }
+                }
+            });
+            previous = span.end;
+        }
+    }
+}
+
+/// Returns the elements of the source, comments and empty lines included
+fn scan(code: &str) -> Result<Vec<Node>, LangError> {
+    let (items, comments) = read_with_comments(code)?;
+    let mut builder = Builder {
+        code,
+        comments: comments.into_iter().peekable(),
+    };
+    Ok(builder.build(&items, 0, code.len()))
+}
+
+/// Returns the element on a single line, or None if it contains a comment
+fn flat(node: &Node) -> Option<String> {
+    match node {
+        Node::Atom(x) => Some(x.to_string()),
+        Node::List(v) => Some(format!(
+            "({})",
+            v.iter()
+                .filter(|n| **n != Node::Blank)
+                .map(flat)
+                .collect::<Option<Vec<String>>>()?
+                .join(" ")
+        )),
+        Node::Sequence(_) | Node::Comment(..) | Node::Blank => None,
+    }
+}
+
+/// Starts a new line, indented by `indent` spaces
+fn new_line(out: &mut String, indent: usize, blank: bool) {
+    if blank {
+        out.push('\n');
+    }
+    out.push('\n');
+    out.push_str(&" ".repeat(indent));
+}
+
+/// Writes the element, starting at the column `indent`
+///
+/// A list that does not fit on the line keeps its leading atoms on its first line,
+/// like `(for i 1 10`, and then has one element per line, indented by 2 spaces
+/// When it starts with a list, its elements are aligned with the first one
+fn write(node: &Node, indent: usize, width: usize, out: &mut String) {
+    let children = match node {
+        Node::List(children) | Node::Sequence(children) => children,
+        Node::Atom(x) | Node::Comment(x, _) => {
+            out.push_str(x);
+            return;
+        }
+        Node::Blank => return,
+    };
+    if let Some(line) = flat(node).filter(|line| indent + line.chars().count() <= width) {
+        out.push_str(&line);
+        return;
+    }
+
+    out.push('(');
+    let mut column = indent + 1;
+    let mut rest = children.iter().peekable();
+    let mut has_head = false;
+    while let Some(Node::Atom(x)) = rest.peek() {
+        let len = x.chars().count();
+        if has_head && column + 1 + len > width {
+            break;
+        }
+        if has_head {
+            out.push(' ');
+            column += 1;
+        }
+        out.push_str(x);
+        column += len;
+        has_head = true;
+        rest.next();
+    }
+    // A condition or a value following the head stays on its line, like `(while (< i n)`
+    if let (true, Some(node @ Node::List(_))) = (has_head, rest.peek()) {
+        let fits = flat(node).filter(|line| column + 1 + line.chars().count() <= width);
+        if let (Some(line), true) = (fits, rest.len() > 1) {
+            out.push(' ');
+            out.push_str(&line);
+            rest.next();
+        }
+    }
+    let body_indent = if has_head { indent + 2 } else { indent + 1 };
+
+    // Whether the current line already has an element, and whether it ends with a comment
+    let mut line_used = has_head;
+    let mut after_comment = false;
+    let mut blank = false;
+    for child in rest {
+        match child {
+            Node::Blank => blank = line_used,
+            Node::Comment(text, trailing) => {
+                if *trailing && line_used {
+                    out.push(' ');
+                } else if line_used {
+                    new_line(out, body_indent, blank);
+                }
+                out.push_str(text);
+                after_comment = true;
+                line_used = true;
+                blank = false;
+            }
+            _ => {
+                if line_used {
+                    new_line(out, body_indent, blank);
+                }
+                write(child, body_indent, width, out);
+                after_comment = false;
+                line_used = true;
+                blank = false;
+            }
+        }
+    }
+    if after_comment {
+        new_line(out, indent, false);
+    }
+    out.push(')');
+}
+
+/// Returns the program with a canonical indentation, and lines at most `width` characters long
+/// when possible; comments and the empty lines between top-level expressions are kept
+pub fn format(code: &str, width: usize) -> Result<String, LangError> {
+    let mut out = String::new();
+    let mut blank = false;
+    for node in scan(code)?.iter() {
+        match node {
+            Node::Blank => blank = true,
+            Node::Comment(text, true) if !out.is_empty() => {
+                out.pop();
+                out.push(' ');
+                out.push_str(text);
+                out.push('\n');
+            }
+            _ => {
+                if blank && !out.is_empty() {
+                    out.push('\n');
+                }
+                blank = false;
+                write(node, 0, width, &mut out);
+                out.push('\n');
+            }
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests_formatter {
+    use crate::formatter::*;
+    use crate::tokens::*;
+    use std::fs;
+
+    #[test]
+    fn test_format() {
+        assert_eq!(format("(+   1\n 2)", 80).unwrap(), "(+ 1 2)\n");
+        assert_eq!(
+            format("((let x 1) (for i 1 10 ((let z (+ x i)) (set x z))) x)", 25).unwrap(),
+            "((let x 1)\n (for i 1 10\n   ((let z (+ x i))\n    (set x z)))\n x)\n"
+        );
+        assert!(format("(+ 1 2", 80).is_err());
+    }
+
+    #[test]
+    fn test_format_sequences() {
+        assert_eq!(
+            format("((let x 1) (print x)) (+ 1 2)", 80).unwrap(),
+            "((let x 1)\n (print x))\n(+ 1 2)\n"
+        );
+        // The arms of a match are not sequences, even when their pattern is a list
+        let code = "(match l ((list x & r) x) (_ 0))\n";
+        assert_eq!(format(code, 80).unwrap(), code);
+    }
+
+    #[test]
+    fn test_format_comments() {
+        let code = "; Sum\n(+ 1 ; one\n   2)\n\n\n(print \"; not a comment\") ; end\n";
+        assert_eq!(
+            format(code, 80).unwrap(),
+            "; Sum\n(+ 1 ; one\n  2)\n\n(print \"; not a comment\") ; end\n"
+        );
+        assert_eq!(format("(+ 1 2 ; two\n)", 80).unwrap(), "(+ 1 2 ; two\n)\n");
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/code");
+        let mut parsed = 0;
+        for entry in fs::read_dir(dir).unwrap() {
+            let code = fs::read_to_string(entry.unwrap().path()).unwrap();
+            for width in [20, DEFAULT_WIDTH] {
+                let formatted = format(&code, width).unwrap();
+                // Some examples are read, but are not valid programs
+                match Expr::token_tree(&code) {
+                    Ok(e) => {
+                        assert_eq!(Expr::token_tree(&formatted).unwrap(), e);
+                        parsed += 1;
+                    }
+                    Err(err) => assert_eq!(
+                        Expr::token_tree(&formatted).unwrap_err().to_string(),
+                        err.to_string()
+                    ),
+                }
+                assert_eq!(read(&formatted).unwrap(), read(&code).unwrap());
+                assert_eq!(format(&formatted, width).unwrap(), formatted);
+            }
+        }
+        assert!(parsed > 0);
+    }
+}
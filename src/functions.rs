@@ -1,22 +1,171 @@
+use crate::errors::*;
+use crate::reader::*;
 use crate::tokens::Expr;
+use crate::types::Type;
+use std::rc::Rc;
+
+/// Parameter of a function:
+/// `NAME`, `(NAME : TYPE)`, `(NAME DEFAULT)`, `(NAME : TYPE DEFAULT)`,
+/// or `& NAME` which receives the list of the remaining arguments
+#[derive(Debug, PartialEq, Clone)]
+pub struct Param {
+    name: String,
+    annotation: Option<Type>,
+    default: Option<Expr>,
+    rest: bool,
+}
+
+impl Param {
+    pub(crate) fn new(
+        name: String,
+        annotation: Option<Type>,
+        default: Option<Expr>,
+        rest: bool,
+    ) -> Self {
+        Param {
+            name,
+            annotation,
+            default,
+            rest,
+        }
+    }
+
+    /// Returns the parameter associated with `NAME`, `(NAME [: TYPE] [DEFAULT])`
+    pub(crate) fn token_tree(e: &Spanned) -> Result<Self, LangError> {
+        if let Some(name) = e.atom() {
+            return Ok(Param::new(name.to_string(), None, None, false));
+        }
+        let items = e.get_items();
+        let name = match items.first() {
+            Some(name) => name.get_atom("a parameter name")?,
+            None => {
+                return Err(LangError::new_syntax_error(
+                    "Expected a parameter name".to_string(),
+                    e.to_string(),
+                ))
+            }
+        };
+        let (annotation, default) = match items {
+            [_, colon, t, default @ ..] if colon.is_atom(":") && default.len() <= 1 => (
+                Some(Type::token_tree(&t.get_atom("a type")?)),
+                default.first(),
+            ),
+            [_, default @ ..] if default.len() <= 1 && !items.iter().any(|x| x.is_atom(":")) => {
+                (None, default.first())
+            }
+            _ => {
+                return Err(LangError::new_syntax_error(
+                    "Expected a parameter (NAME [: TYPE] [DEFAULT])".to_string(),
+                    e.to_string(),
+                ))
+            }
+        };
+        Ok(Param::new(
+            name,
+            annotation,
+            default.map(Expr::from_sexp).transpose()?,
+            false,
+        ))
+    }
+
+    /// Returns the parameters associated with `PARAM1 ... PARAMn [& REST]`
+    pub(crate) fn token_tree_list(v: &[Spanned]) -> Result<Vec<Self>, LangError> {
+        let mut params = Vec::new();
+        let mut iter = v.iter();
+        while let Some(e) = iter.next() {
+            if e.is_atom("&") {
+                match iter.next() {
+                    Some(rest) => params.push(Param::new(
+                        rest.get_atom("a parameter name")?,
+                        None,
+                        None,
+                        true,
+                    )),
+                    None => {
+                        return Err(LangError::new_syntax_error(
+                            "Expected a parameter name after &".to_string(),
+                            e.to_string(),
+                        ))
+                    }
+                }
+            } else {
+                params.push(Param::token_tree(e)?);
+            }
+        }
+        Ok(params)
+    }
+
+    pub(crate) fn get_name(&self) -> String {
+        self.name.clone()
+    }
+
+    pub(crate) fn get_annotation(&self) -> Option<Type> {
+        self.annotation.clone()
+    }
+
+    pub(crate) fn get_default(&self) -> Option<Expr> {
+        self.default.clone()
+    }
+
+    pub(crate) fn is_rest(&self) -> bool {
+        self.rest
+    }
+}
 
 #[derive(Debug, Clone)]
-pub(crate) struct Function {
+pub struct Function {
     name: String,
-    args: Vec<String>,
-    core: Box<Expr>,
+    params: Vec<Param>,
+    ret: Option<Type>,
+    core: Rc<Expr>,
 }
 
 impl Function {
-    pub(crate) fn new(name: String, args: Vec<String>, core: Box<Expr>) -> Self {
-        Function { name, args, core }
+    pub(crate) fn new(name: String, params: Vec<Param>, ret: Option<Type>, core: Rc<Expr>) -> Self {
+        Function {
+            name,
+            params,
+            ret,
+            core,
+        }
+    }
+
+    pub(crate) fn get_name(&self) -> String {
+        self.name.clone()
+    }
+
+    /// Returns the minimum and the maximum (if any) number of arguments of the function
+    pub(crate) fn arity(&self) -> (usize, Option<usize>) {
+        arity(&self.params)
     }
 
     pub(crate) fn get_args(&self) -> Vec<String> {
-        self.args.clone()
+        self.params.iter().map(|p| p.get_name()).collect()
     }
 
-    pub(crate) fn get_core(&self) -> Box<Expr> {
+    pub(crate) fn get_params(&self) -> Vec<Param> {
+        self.params.clone()
+    }
+
+    pub(crate) fn get_ret(&self) -> Option<Type> {
+        self.ret.clone()
+    }
+
+    pub(crate) fn get_core(&self) -> Rc<Expr> {
         self.core.clone()
     }
 }
+
+/// Returns the minimum and the maximum (if any) number of arguments accepted by the parameters
+pub(crate) fn arity(params: &[Param]) -> (usize, Option<usize>) {
+    let positional: Vec<&Param> = params.iter().filter(|p| !p.is_rest()).collect();
+    let min = positional
+        .iter()
+        .rposition(|p| p.get_default().is_none())
+        .map_or(0, |i| i + 1);
+    if params.iter().any(|p| p.is_rest()) {
+        (min, None)
+    } else {
+        (min, Some(positional.len()))
+    }
+}
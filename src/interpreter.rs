@@ -0,0 +1,390 @@
+use crate::capabilities::*;
+use crate::context::*;
+use crate::convert::{from_expr_with, to_expr_with};
+use crate::enums::*;
+use crate::errors::*;
+use crate::functions::*;
+use crate::native::*;
+use crate::tokens::*;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+/// Stack size in bytes of the thread on which `rusil` evaluates programs, with half of it as
+/// the limit of the stack used by an evaluation, so that the limit on nested calls is reached
+/// first even in a debug build, where each call takes tens of kilobytes of the stack
+pub const STACK_SIZE: usize = 256 * 1024 * 1024;
+
+/// Interpreter keeping the variables, functions and enums from one evaluation to the next
+pub struct Interpreter {
+    variables: HashMap<String, Expr>,
+    functions: HashMap<String, Function>,
+    enums: HashMap<String, Vec<Variant>>,
+    context: Context,
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Interpreter::new()
+    }
+}
+
+impl Interpreter {
+    /// Returns an interpreter reading the standard input and writing the standard output
+    pub fn new() -> Self {
+        Interpreter::with_context(Context::stdio(vec![]))
+    }
+
+    /// Returns an interpreter with the given arguments and streams
+    pub fn with_context(context: Context) -> Self {
+        Interpreter {
+            variables: HashMap::new(),
+            functions: HashMap::new(),
+            enums: builtin_enums(),
+            context,
+        }
+    }
+
+    pub fn get_context(&mut self) -> &mut Context {
+        &mut self.context
+    }
+
+    /// Evaluates the token tree and returns its value
+    pub fn eval(&mut self, e: &Expr) -> Result<Expr, LangError> {
+        self.context.start();
+        e.evaluate(
+            &mut self.variables,
+            &mut self.functions,
+            &mut self.enums,
+            &mut self.context,
+        )
+    }
+
+    /// Evaluates the program and returns its value
+    pub fn eval_str(&mut self, code: &str) -> Result<Expr, LangError> {
+        self.eval(&Expr::token_tree(code)?)
+    }
+
+    /// Evaluates the program of the file and returns its value
+    pub fn eval_file<P: AsRef<Path>>(&mut self, path: P) -> Result<Expr, LangError> {
+        let code = fs::read_to_string(&path).map_err(|e| {
+            LangError::new_io_error(e.to_string(), path.as_ref().display().to_string())
+        })?;
+        self.eval_str(&code)
+    }
+
+    /// Returns the value of the variable, if it is defined
+    pub fn get_var(&self, name: &str) -> Option<Expr> {
+        self.variables.get(name).cloned()
+    }
+
+    /// Defines the variable, or changes its value
+    pub fn set_var(&mut self, name: &str, value: Expr) {
+        self.variables.insert(name.to_string(), value);
+    }
+
+    /// Returns the Rusil value of the Rust value, like `convert::to_expr`
+    /// The fields of a struct or an enum item declared by the program are put in the order
+    /// of its declaration, matched by their names
+    pub fn to_expr<T: Serialize + ?Sized>(&self, value: &T) -> Result<Expr, LangError> {
+        to_expr_with(value, &self.enums)
+    }
+
+    /// Returns the Rust value of the Rusil value, like `convert::from_expr`
+    /// The fields of a struct or an enum item declared by the program are matched by their names
+    pub fn from_expr<T: DeserializeOwned>(&self, value: Expr) -> Result<T, LangError> {
+        from_expr_with(value, &self.enums)
+    }
+
+    /// Limits the number of expressions the next evaluations can evaluate, together
+    /// When the budget is exhausted, they raise a `ResourceExhausted` error
+    pub fn set_fuel(&mut self, fuel: Option<u64>) {
+        self.context.set_fuel(fuel);
+    }
+
+    /// Returns the number of expressions that can still be evaluated, if it is limited
+    pub fn get_fuel(&self) -> Option<u64> {
+        self.context.get_fuel()
+    }
+
+    /// Limits the time taken by each evaluation
+    /// When it runs longer, it raises a `ResourceExhausted` error
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.context.set_timeout(timeout);
+    }
+
+    /// Limits the approximate size in bytes of the strings, lists and enum values created by the
+    /// next evaluations, together, like the fuel, even if they are no longer used
+    /// Creating more raises a `ResourceExhausted` error
+    pub fn set_allocation_budget(&mut self, allocation_budget: Option<usize>) {
+        self.context.set_allocation_budget(allocation_budget);
+    }
+
+    /// Limits the number of nested function calls, 1000 by default
+    /// A deeper recursion raises a `ResourceExhausted` error, like one going past the stack
+    /// limit of `set_max_stack` first, as in a debug build on a default thread
+    pub fn set_max_call_depth(&mut self, max_call_depth: Option<usize>) {
+        self.context.set_max_call_depth(max_call_depth);
+    }
+
+    /// Limits the size in bytes of the stack used by each evaluation, 1 MiB by default, which
+    /// fits the threads spawned by the standard library
+    /// A deeper evaluation raises a `ResourceExhausted` error instead of overflowing the stack:
+    /// a higher limit, or `None`, needs a thread with a larger stack, like `STACK_SIZE`
+    pub fn set_max_stack(&mut self, max_stack: Option<usize>) {
+        self.context.set_max_stack(max_stack);
+    }
+
+    /// Allows the program to use only the given capabilities, all of them by default
+    /// Using another one raises a `PermissionDenied` error
+    pub fn set_capabilities(&mut self, capabilities: &[Capability]) {
+        self.context.set_capabilities(capabilities);
+    }
+
+    /// Registers a Rust closure, called from the program with `(call NAME ARG1 ... ARGn)`
+    /// Its arguments and result are converted with `FromRusil` and `IntoRusil`
+    pub fn register<Args, F: NativeFunction<Args> + 'static>(&mut self, name: &str, f: F) {
+        self.context.register(Native::new(name, f));
+    }
+
+    /// Calls the function defined by the program, or registered, with the arguments
+    pub fn call_function(&mut self, name: &str, args: Vec<Expr>) -> Result<Expr, LangError> {
+        self.context.start();
+        Expr::call(
+            name,
+            args,
+            &mut self.variables,
+            &mut self.functions,
+            &mut self.enums,
+            &mut self.context,
+            name.to_string(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests_interpreter {
+    use crate::interpreter::*;
+
+    #[test]
+    fn test_eval_str_keeps_state() {
+        let mut interpreter = Interpreter::new();
+        interpreter.eval_str("(let x 2)").unwrap();
+        interpreter.eval_str("(def double n (* 2 n))").unwrap();
+        assert_eq!(
+            interpreter.eval_str("(call double x)").unwrap(),
+            Expr::Number(4)
+        );
+    }
+
+    #[test]
+    fn test_variables() {
+        let mut interpreter = Interpreter::new();
+        interpreter.set_var("name", Expr::Str("rusil".to_string()));
+        interpreter.eval_str("(let size 3)").unwrap();
+        assert_eq!(interpreter.get_var("size"), Some(Expr::Number(3)));
+        assert_eq!(
+            interpreter.eval_str("name").unwrap(),
+            Expr::Str("rusil".to_string())
+        );
+        assert_eq!(interpreter.get_var("undefined"), None);
+    }
+
+    #[test]
+    fn test_call_function() {
+        let mut interpreter = Interpreter::new();
+        interpreter
+            .eval_file(concat!(env!("CARGO_MANIFEST_DIR"), "/code/fact_rec.rsl"))
+            .unwrap();
+        assert_eq!(
+            interpreter
+                .call_function("fact", vec![Expr::Number(5)])
+                .unwrap(),
+            Expr::Number(120)
+        );
+        assert!(interpreter.call_function("fact", vec![]).is_err());
+        assert!(interpreter.call_function("nope", vec![]).is_err());
+    }
+
+    #[test]
+    fn test_fuel() {
+        let mut interpreter = Interpreter::new();
+        interpreter.set_fuel(Some(1000));
+        interpreter.eval_str("(let x 0)").unwrap();
+        assert!(interpreter.get_fuel().unwrap() < 1000);
+        assert!(matches!(
+            interpreter.eval_str("(while true (set x (+ x 1)))"),
+            Err(LangError::ResourceExhausted(_))
+        ));
+        assert_eq!(interpreter.get_fuel(), Some(0));
+        interpreter.set_fuel(None);
+        assert_eq!(interpreter.eval_str("(+ 1 2)").unwrap(), Expr::Number(3));
+    }
+
+    #[test]
+    fn test_timeout() {
+        let mut interpreter = Interpreter::new();
+        interpreter.set_timeout(Some(Duration::from_millis(50)));
+        assert!(matches!(
+            interpreter.eval_str("(while true ())"),
+            Err(LangError::ResourceExhausted(_))
+        ));
+        // The clock starts again with each evaluation
+        assert_eq!(interpreter.eval_str("(+ 1 2)").unwrap(), Expr::Number(3));
+    }
+
+    #[test]
+    fn test_allocation_budget() {
+        let mut interpreter = Interpreter::new();
+        interpreter.set_allocation_budget(Some(10_000));
+        interpreter
+            .eval_str("(def grow l n (if (= n 0) l (call grow (list l l) (- n 1))))")
+            .unwrap();
+        assert!(interpreter
+            .call_function("grow", vec![Expr::Number(0), Expr::Number(3)])
+            .is_ok());
+        assert!(matches!(
+            interpreter.call_function("grow", vec![Expr::Number(0), Expr::Number(30)]),
+            Err(LangError::ResourceExhausted(_))
+        ));
+        interpreter.register("big", || "a".repeat(100_000));
+        interpreter.set_allocation_budget(Some(10_000));
+        assert!(matches!(
+            interpreter.eval_str("(call big)"),
+            Err(LangError::ResourceExhausted(_))
+        ));
+    }
+
+    #[test]
+    fn test_max_call_depth() {
+        let mut interpreter = Interpreter::new();
+        interpreter.set_max_call_depth(Some(20));
+        interpreter
+            .eval_str(
+                "((def loop n (call loop n)) (def down n (if (= n 0) 0 (call down (- n 1)))))",
+            )
+            .unwrap();
+        assert!(matches!(
+            interpreter.eval_str("(call loop 1)"),
+            Err(LangError::ResourceExhausted(_))
+        ));
+        // The calls that failed are not counted in the next evaluations
+        assert_eq!(
+            interpreter.eval_str("(call down 10)").unwrap(),
+            Expr::Number(0)
+        );
+        assert!(matches!(
+            interpreter.call_function("down", vec![Expr::Number(30)]),
+            Err(LangError::ResourceExhausted(_))
+        ));
+    }
+
+    #[test]
+    fn test_max_call_depth_default() {
+        // With the default limits, on the stack of a test thread, a deep recursion is stopped
+        // before overflowing it, whatever the build
+        for limit in [Some(MAX_CALL_DEPTH), None] {
+            let mut interpreter = Interpreter::new();
+            interpreter.set_max_call_depth(limit);
+            interpreter
+                .eval_str("(def down n (if (= n 0) 0 (+ 1 (call down (- n 1)))))")
+                .unwrap();
+            assert!(matches!(
+                interpreter.eval_str("(call down 100000)"),
+                Err(LangError::ResourceExhausted(_))
+            ));
+            assert_eq!(
+                interpreter.eval_str("(call down 10)").unwrap(),
+                Expr::Number(10)
+            );
+        }
+    }
+
+    #[test]
+    fn test_allocation_budget_total() {
+        let mut interpreter = Interpreter::new();
+        interpreter.set_allocation_budget(Some(10_000));
+        // Each list is small, and no longer used after the next one is created, but together
+        // they are larger than the budget
+        assert!(matches!(
+            interpreter.eval_str("(for i 0 10000 (list i))"),
+            Err(LangError::ResourceExhausted(_))
+        ));
+        interpreter.set_allocation_budget(Some(10_000));
+        assert!(interpreter.eval_str("(for i 0 10 (list i))").is_ok());
+    }
+
+    #[test]
+    fn test_allocation_budget_sources() {
+        let mut interpreter = Interpreter::new();
+        interpreter
+            .eval_str(
+                "((def f & r r) (def tail l (match l ((list _ & r) r) (_ l))) \
+                 (enum e a b c))",
+            )
+            .unwrap();
+        interpreter.set_var("l", Expr::List(vec![Expr::Number(1), Expr::Number(2)]));
+        // Rest parameters, rest patterns and variants create lists counted in the budget
+        for (code, budget) in [
+            ("(for i 0 1000 (call f 1 2 3))", 10_000),
+            ("(call tail l)", 1),
+            ("(for i 0 1000 (variants e))", 10_000),
+        ] {
+            interpreter.set_allocation_budget(Some(budget));
+            assert!(
+                matches!(
+                    interpreter.eval_str(code),
+                    Err(LangError::ResourceExhausted(_))
+                ),
+                "no error on: {}",
+                code
+            );
+        }
+    }
+
+    #[test]
+    fn test_capabilities() {
+        let mut interpreter = Interpreter::new();
+        interpreter.set_capabilities(&[Capability::Stdout]);
+        for code in ["(input)", "(env \"HOME\")"] {
+            assert!(matches!(
+                interpreter.eval_str(code),
+                Err(LangError::PermissionDenied(_))
+            ));
+        }
+        interpreter.set_capabilities(&[]);
+        match interpreter.eval_str("(print 1)") {
+            Err(LangError::PermissionDenied(e)) => {
+                assert_eq!(e.get_capability(), Capability::Stdout)
+            }
+            _ => panic!("print should be denied"),
+        }
+        assert_eq!(interpreter.eval_str("(+ 1 2)").unwrap(), Expr::Number(3));
+    }
+
+    #[test]
+    fn test_eval_file_missing() {
+        assert!(Interpreter::new().eval_file("missing.rsl").is_err());
+    }
+
+    #[test]
+    fn test_error_span() {
+        // The error is at the innermost expression raising it, or containing the faulty name
+        let code = "(+ (/ 1 0)\n   (/ 1 0))";
+        let error = Interpreter::new().eval_str(code).unwrap_err();
+        let span = error.get_span().unwrap();
+        assert_eq!(&code[span.start..span.end], "(/ 1 0)");
+        let error = Interpreter::new()
+            .eval_str("(let y 1)\n(+ y x)")
+            .unwrap_err();
+        assert_eq!(error.get_span().map(|s| s.start), Some(10));
+        assert!(Interpreter::new()
+            .eval_str("(exit 1)")
+            .unwrap_err()
+            .get_span()
+            .is_none());
+    }
+}
@@ -0,0 +1,44 @@
+//! Rusil: a small Lisp-like language, usable as a library
+//!
+//! ```
+//! use rusil::{Expr, Interpreter};
+//!
+//! let mut interpreter = Interpreter::new();
+//! interpreter.set_var("width", Expr::Number(3));
+//! interpreter.eval_str("(def area h (* width h))").unwrap();
+//! let area = interpreter.call_function("area", vec![Expr::Number(4)]).unwrap();
+//! assert_eq!(area, Expr::Number(12));
+//! ```
+
+pub mod ast;
+pub mod capabilities;
+pub mod checker;
+pub mod context;
+pub mod convert;
+pub mod coverage;
+pub mod debugger;
+pub mod enums;
+pub mod errors;
+pub mod formatter;
+pub mod functions;
+pub mod interpreter;
+pub mod lsp;
+pub mod native;
+pub mod patterns;
+pub mod profiler;
+pub mod reader;
+#[cfg(test)]
+mod tests_fuzz;
+pub mod tokens;
+pub mod tracer;
+pub mod types;
+
+pub use crate::capabilities::Capability;
+pub use crate::context::Context;
+pub use crate::convert::{from_expr, to_expr};
+pub use crate::errors::LangError;
+pub use crate::functions::Function;
+pub use crate::interpreter::Interpreter;
+pub use crate::native::{FromRusil, IntoRusil};
+pub use crate::tokens::Expr;
+pub use crate::types::Type;
@@ -0,0 +1,474 @@
+use crate::checker::Checker;
+use crate::errors::*;
+use crate::formatter::{format, DEFAULT_WIDTH};
+use crate::functions::*;
+use crate::reader::*;
+use crate::tokens::*;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+
+/// Words completed by the server
+const KEYWORDS: [&str; 32] = [
+    "+", "-", "*", "/", "%", "=", "!=", ">", ">=", "<", "<=", "&&", "||", "!", "if", "let", "set",
+    "def", "call", "enum", "print", "input", "args", "env", "exit", "while", "for", "match",
+    "variants", "list", "true", "false",
+];
+
+/// JSON-RPC error codes
+const PARSE_ERROR: i64 = -32700;
+const METHOD_NOT_FOUND: i64 = -32601;
+const REQUEST_FAILED: i64 = -32803;
+
+/// Size of the largest message body read, so that a wrong header cannot exhaust the memory
+const MAX_CONTENT_LENGTH: usize = 64 * 1024 * 1024;
+
+/// Language server, keeping the text of the documents opened by the editor
+#[derive(Default)]
+pub struct Server {
+    documents: HashMap<String, String>,
+    shutdown: bool,
+    exit: bool,
+}
+
+/// Returns the LSP position, with a column in UTF-16 code units, of the byte offset
+fn position(code: &str, offset: usize) -> Value {
+    let before = &code[..offset];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    json!({
+        "line": before.matches('\n').count(),
+        "character": before[line_start..].encode_utf16().count(),
+    })
+}
+
+fn range(code: &str, start: usize, end: usize) -> Value {
+    json!({"start": position(code, start), "end": position(code, end)})
+}
+
+/// Returns the byte offset of the LSP position
+fn offset(code: &str, position: &Value) -> usize {
+    let line = position["line"].as_u64().unwrap_or_default() as usize;
+    let character = position["character"].as_u64().unwrap_or_default() as usize;
+    let line_start: usize = code.split_inclusive('\n').take(line).map(str::len).sum();
+    let mut units = 0;
+    for (i, c) in code[line_start..].char_indices() {
+        if units >= character || c == '\n' {
+            return line_start + i;
+        }
+        units += c.len_utf16();
+    }
+    code.len()
+}
+
+/// Returns the name under the byte offset, with its start and end
+fn word_at(code: &str, offset: usize) -> Option<(String, usize, usize)> {
+    let is_word = |c: char| !c.is_whitespace() && !"();\"".contains(c);
+    let start = code[..offset]
+        .char_indices()
+        .rev()
+        .take_while(|(_, c)| is_word(*c))
+        .last()
+        .map_or(offset, |(i, _)| i);
+    let end = code[offset..]
+        .char_indices()
+        .find(|(_, c)| !is_word(*c))
+        .map_or(code.len(), |(i, _)| offset + i);
+    Some((code[start..end].to_string(), start, end)).filter(|(w, _, _)| !w.is_empty())
+}
+
+/// Returns the S-expressions of the source, parents before their items
+fn flatten(expressions: &[Spanned]) -> Vec<&Spanned> {
+    let mut all = Vec::new();
+    for e in expressions {
+        all.push(e);
+        all.append(&mut flatten(e.get_items()));
+    }
+    all
+}
+
+/// Returns the message of the error, without its colors and its expression
+fn error_message(e: &LangError) -> String {
+    e.to_string()
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.contains('\x1B'))
+        .map(|line| line.trim_start_matches(">>> "))
+        .collect::<Vec<&str>>()
+        .join("\n")
+}
+
+/// Returns the range of the source where the error is, or its start if it is unknown
+fn error_range(code: &str, e: &LangError) -> Value {
+    match e.get_span() {
+        Some(span) => range(code, span.start, span.end),
+        None => range(code, 0, 0),
+    }
+}
+
+/// Returns the errors of the parser, or else of the type checker
+fn diagnostics(code: &str) -> Vec<Value> {
+    let errors = match Expr::token_tree(code) {
+        Ok(tree) => Checker::new().check(&tree),
+        Err(e) => vec![e],
+    };
+    errors
+        .iter()
+        .map(|e| {
+            json!({
+                "range": error_range(code, e),
+                "severity": 1,
+                "source": "rusil",
+                "message": error_message(e),
+            })
+        })
+        .collect()
+}
+
+/// Returns the functions defined in the token tree
+fn functions(e: &Expr) -> Vec<Function> {
+    let mut all = Vec::new();
+    if let Expr::Define(name, params, ret, core, _) = e {
+        if let Expr::Var(name) = name.as_ref() {
+            all.push(Function::new(
+                name.to_string(),
+                params.clone(),
+                ret.clone(),
+                core.clone(),
+            ));
+        }
+    }
+    for child in e.children() {
+        all.append(&mut functions(child));
+    }
+    all
+}
+
+fn response(id: Value, result: Value) -> Value {
+    json!({"jsonrpc": "2.0", "id": id, "result": result})
+}
+
+fn error(id: Value, code: i64, message: &str) -> Value {
+    json!({"jsonrpc": "2.0", "id": id, "error": {"code": code, "message": message}})
+}
+
+impl Server {
+    pub fn new() -> Self {
+        Server::default()
+    }
+
+    /// Handles a message of the client, and returns the messages to send back
+    pub fn handle(&mut self, message: &Value) -> Vec<Value> {
+        let method = message["method"].as_str().unwrap_or_default();
+        let params = &message["params"];
+        let id = match message.get("id") {
+            Some(id) => id.clone(),
+            // Notifications have no response
+            None => return self.notify(method, params),
+        };
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+        let code = self.documents.get(uri).cloned().unwrap_or_default();
+        let code = code.as_str();
+        let offset = offset(code, &params["position"]);
+        let result = match method {
+            "initialize" => json!({
+                "capabilities": {
+                    "textDocumentSync": 1,
+                    "definitionProvider": true,
+                    "hoverProvider": true,
+                    "completionProvider": {},
+                    "documentFormattingProvider": true,
+                },
+                "serverInfo": {"name": "rusil-lsp"},
+            }),
+            "shutdown" => {
+                self.shutdown = true;
+                Value::Null
+            }
+            "textDocument/definition" => self.definition(uri, code, offset),
+            "textDocument/hover" => Server::hover(code, offset),
+            "textDocument/completion" => Value::Array(
+                KEYWORDS
+                    .iter()
+                    .map(|k| json!({"label": k, "kind": 14}))
+                    .collect(),
+            ),
+            "textDocument/formatting" => match format(code, DEFAULT_WIDTH) {
+                Ok(formatted) => json!([{
+                    "range": range(code, 0, code.len()),
+                    "newText": formatted,
+                }]),
+                Err(e) => return vec![error(id, REQUEST_FAILED, &error_message(&e))],
+            },
+            _ => return vec![error(id, METHOD_NOT_FOUND, method)],
+        };
+        vec![response(id, result)]
+    }
+
+    /// Handles a notification, and returns the diagnostics to publish
+    fn notify(&mut self, method: &str, params: &Value) -> Vec<Value> {
+        let uri = params["textDocument"]["uri"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string();
+        let text = match method {
+            "textDocument/didOpen" => params["textDocument"]["text"].as_str(),
+            // The whole text is sent at each change
+            "textDocument/didChange" => params["contentChanges"]
+                .as_array()
+                .and_then(|changes| changes.last())
+                .and_then(|change| change["text"].as_str()),
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                return vec![json!({
+                    "jsonrpc": "2.0",
+                    "method": "textDocument/publishDiagnostics",
+                    "params": {"uri": uri, "diagnostics": []},
+                })];
+            }
+            "exit" => {
+                self.exit = true;
+                None
+            }
+            _ => None,
+        };
+        match text {
+            Some(text) => {
+                let diagnostics = diagnostics(text);
+                self.documents.insert(uri.to_string(), text.to_string());
+                vec![json!({
+                    "jsonrpc": "2.0",
+                    "method": "textDocument/publishDiagnostics",
+                    "params": {"uri": uri, "diagnostics": diagnostics},
+                })]
+            }
+            None => vec![],
+        }
+    }
+
+    /// Returns the locations where the name under the cursor is given by `def`, `let` or `enum`
+    fn definition(&self, uri: &str, code: &str, offset: usize) -> Value {
+        let (name, _, _) = match word_at(code, offset) {
+            Some(word) => word,
+            None => return Value::Null,
+        };
+        let expressions = read_spanned(code).unwrap_or_default();
+        let locations: Vec<Value> = flatten(&expressions)
+            .into_iter()
+            .filter_map(|s| match s.get_items() {
+                [keyword, defined, ..]
+                    if ["def", "let", "enum"].contains(&keyword.to_sexp().to_string().as_str())
+                        && defined.to_sexp() == SExp::Atom(name.to_string()) =>
+                {
+                    Some(json!({"uri": uri, "range": range(code, defined.start, defined.end)}))
+                }
+                _ => None,
+            })
+            .collect();
+        Value::Array(locations)
+    }
+
+    /// Returns the parameters of the function under the cursor, and its inferred type
+    fn hover(code: &str, offset: usize) -> Value {
+        let (name, start, end) = match word_at(code, offset) {
+            Some(word) => word,
+            None => return Value::Null,
+        };
+        let tree = match Expr::token_tree(code) {
+            Ok(tree) => tree,
+            Err(_) => return Value::Null,
+        };
+        let function = match functions(&tree).into_iter().find(|f| f.get_name() == name) {
+            Some(function) => function,
+            None => return Value::Null,
+        };
+        let mut lines = vec![format!("(def {} {})", name, function.get_args().join(" "))];
+        let mut checker = Checker::new();
+        checker.check(&tree);
+        let prefix = format!("{}: ", name);
+        lines.extend(
+            checker
+                .signatures()
+                .into_iter()
+                .filter(|s| s.starts_with(&prefix)),
+        );
+        json!({
+            "contents": {"kind": "markdown", "value": format!("```\n{}\n```", lines.join("\n"))},
+            "range": range(code, start, end),
+        })
+    }
+}
+
+/// Reads a message framed by a `Content-Length` header
+/// Returns None at the end of the input
+fn read_message(input: &mut dyn BufRead) -> std::io::Result<Option<Vec<u8>>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() && length.is_some() {
+            break;
+        }
+        if let Some(n) = line.strip_prefix("Content-Length:") {
+            length = n.trim().parse().ok();
+        }
+    }
+    let length = length.unwrap_or_default();
+    if length > MAX_CONTENT_LENGTH {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!(
+                "Content-Length {} is above the maximum of {}",
+                length, MAX_CONTENT_LENGTH
+            ),
+        ));
+    }
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    Ok(Some(body))
+}
+
+fn write_message(output: &mut dyn Write, message: &Value) -> std::io::Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
+
+/// Answers the messages of the client until it exits
+/// Returns the exit status: 0 if the client asked for a shutdown before, 1 otherwise
+pub fn serve(input: &mut dyn BufRead, output: &mut dyn Write) -> Result<i32, LangError> {
+    let io_error = |e: std::io::Error| LangError::new_io_error(e.to_string(), "lsp".to_string());
+    let mut server = Server::new();
+    while let Some(body) = read_message(input).map_err(io_error)? {
+        let responses = match serde_json::from_slice::<Value>(&body) {
+            Ok(message) => server.handle(&message),
+            Err(e) => vec![error(Value::Null, PARSE_ERROR, &e.to_string())],
+        };
+        for response in responses.iter() {
+            write_message(output, response).map_err(io_error)?;
+        }
+        if server.exit {
+            break;
+        }
+    }
+    Ok(if server.shutdown { 0 } else { 1 })
+}
+
+#[cfg(test)]
+mod tests_lsp {
+    use crate::lsp::*;
+    use std::io::Cursor;
+
+    const URI: &str = "file:///fact.rsl";
+    const CODE: &str = "(def fact n (if (<= n 1) 1 (* n (call fact (- n 1)))))\n(call fact x)\n";
+
+    /// Client sending the requests through `serve`, and returning the messages received
+    fn exchange(messages: &[Value]) -> (i32, Vec<Value>) {
+        let mut input = Vec::new();
+        for m in messages {
+            write_message(&mut input, m).unwrap();
+        }
+        let mut output = Vec::new();
+        let status = serve(&mut Cursor::new(input), &mut output).unwrap();
+        let mut output = Cursor::new(output);
+        let mut received = Vec::new();
+        while let Some(body) = read_message(&mut output).unwrap() {
+            received.push(serde_json::from_slice(&body).unwrap());
+        }
+        (status, received)
+    }
+
+    fn request(id: i64, method: &str, params: Value) -> Value {
+        json!({"jsonrpc": "2.0", "id": id, "method": method, "params": params})
+    }
+
+    fn at(line: usize, character: usize) -> Value {
+        json!({"textDocument": {"uri": URI}, "position": {"line": line, "character": character}})
+    }
+
+    #[test]
+    fn test_session() {
+        let open = json!({"jsonrpc": "2.0", "method": "textDocument/didOpen",
+            "params": {"textDocument": {"uri": URI, "languageId": "rusil", "version": 1, "text": CODE}}});
+        let (status, received) = exchange(&[
+            request(1, "initialize", json!({})),
+            open,
+            request(2, "textDocument/definition", at(1, 8)),
+            request(3, "textDocument/hover", at(1, 7)),
+            request(4, "textDocument/completion", at(0, 0)),
+            request(5, "textDocument/formatting", at(0, 0)),
+            request(6, "unknown", json!({})),
+            request(7, "shutdown", Value::Null),
+            json!({"jsonrpc": "2.0", "method": "exit"}),
+        ]);
+        assert_eq!(status, 0);
+        assert_eq!(received.len(), 8);
+        assert_eq!(received[0]["result"]["capabilities"]["hoverProvider"], true);
+        // `x` is not defined
+        let diagnostics = &received[1]["params"]["diagnostics"];
+        assert_eq!(
+            diagnostics[0]["range"]["start"],
+            json!({"line": 1, "character": 0})
+        );
+        assert!(diagnostics[0]["message"].as_str().unwrap().contains("x"));
+        assert_eq!(
+            received[2]["result"][0]["range"],
+            json!({"start": {"line": 0, "character": 5}, "end": {"line": 0, "character": 9}})
+        );
+        let hover = received[3]["result"]["contents"]["value"].as_str().unwrap();
+        assert!(hover.contains("(def fact n)"));
+        assert!(received[4]["result"]
+            .as_array()
+            .unwrap()
+            .contains(&json!({"label": "match", "kind": 14})));
+        assert!(received[5]["result"][0]["newText"]
+            .as_str()
+            .unwrap()
+            .starts_with("(def fact n"));
+        assert_eq!(received[6]["error"]["code"], METHOD_NOT_FOUND);
+        assert_eq!(received[7]["result"], Value::Null);
+    }
+
+    #[test]
+    fn test_diagnostics() {
+        assert!(diagnostics("(+ 1 2)").is_empty());
+        let syntax = &diagnostics("(+ 1\n 2")[0];
+        assert_eq!(syntax["range"]["start"], json!({"line": 0, "character": 0}));
+        let types = &diagnostics("(let x 1)\n(+ x \"a\")")[0];
+        assert_eq!(
+            types["range"],
+            json!({"start": {"line": 1, "character": 0}, "end": {"line": 1, "character": 9}})
+        );
+        assert!(types["message"].as_str().unwrap().starts_with("TypeError"));
+        // The same expression written twice gives two ranges
+        let twice = diagnostics("(+ 1 \"a\")\n(+ 1 \"a\")");
+        assert_eq!(
+            twice[1]["range"]["start"],
+            json!({"line": 1, "character": 0})
+        );
+        let unexpected = &diagnostics("(+ 1 2))")[0];
+        assert_eq!(
+            unexpected["range"],
+            json!({"start": {"line": 0, "character": 7}, "end": {"line": 0, "character": 8}})
+        );
+    }
+
+    #[test]
+    fn test_content_length_limit() {
+        let header = format!("Content-Length: {}\r\n\r\n{{}}", MAX_CONTENT_LENGTH + 1);
+        let mut output = Vec::new();
+        assert!(serve(&mut Cursor::new(header), &mut output).is_err());
+        assert!(output.is_empty());
+    }
+
+    #[test]
+    fn test_positions() {
+        let code = "(print \"é\")\n(x)";
+        assert_eq!(offset(code, &json!({"line": 1, "character": 1})), 14);
+        assert_eq!(position(code, 10), json!({"line": 0, "character": 9}));
+        assert_eq!(word_at(code, 15), Some(("x".to_string(), 14, 15)));
+        assert_eq!(word_at(code, 16), None);
+    }
+}
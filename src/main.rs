@@ -1,27 +1,290 @@
-pub mod errors;
-pub mod functions;
-pub mod tokens;
-pub mod types;
-use crate::errors::*;
-use crate::tokens::*;
+mod cli;
+use crate::cli::*;
+use rusil::ast::Node;
+use rusil::checker::Checker;
+use rusil::coverage::{cover, report};
+use rusil::formatter::format;
+use rusil::interpreter::STACK_SIZE;
+use rusil::patterns::match_warnings;
+use rusil::profiler::profile;
+use rusil::reader::is_complete;
+use rusil::tracer::trace;
+use rusil::{Context, Expr, Interpreter, LangError};
 use std::env;
-use std::error::Error;
-use std::fs::File;
+use std::fs;
 use std::io::prelude::*;
+use std::path::Path;
+use std::process;
+use std::thread;
 
-// for argument in env::args()
-fn main() {
-    assert_eq!(env::args().len(), 2);
-    let args: Vec<_> = env::args().collect();
-    let mut file = File::open(args[1].to_string()).unwrap();
+/// Exit code of a command that succeeded
+const SUCCESS: i32 = 0;
+/// Exit code of a command that failed, when no error says otherwise
+const FAILURE: i32 = 1;
+
+/// Returns the code of the program
+fn read_code(source: &Source) -> Result<String, LangError> {
+    let io_error = |e: std::io::Error| LangError::new_io_error(e.to_string(), source.name());
+    match source {
+        Source::File(file_name) => fs::read_to_string(file_name).map_err(io_error),
+        Source::Stdin => {
+            let mut code = String::new();
+            std::io::stdin()
+                .read_to_string(&mut code)
+                .map_err(io_error)?;
+            Ok(code)
+        }
+        Source::Inline(code) => Ok(code.to_string()),
+    }
+}
+
+/// Returns the context of a program reading the standard input and writing the standard output
+/// Its evaluation can use half of the stack of the thread it runs on
+fn stdio_context(args: &[String]) -> Context {
+    let mut context = Context::stdio(args.to_vec());
+    context.set_max_stack(Some(STACK_SIZE / 2));
+    context
+}
+
+/// Returns the token tree of the program, after printing its warnings
+fn parse(code: &str) -> Result<Expr, LangError> {
+    let e = Expr::token_tree(code)?;
+    for w in match_warnings(&e) {
+        eprintln!("{}", w);
+    }
+    Ok(e)
+}
+
+/// Executes the program
+fn run(source: &Source, args: &[String], options: &RunOptions) -> Result<(), LangError> {
+    let code = read_code(source)?;
+    let e = parse(&code)?;
+    let context = stdio_context(args);
+    let result = match &options.trace {
+        Some(filter) => trace(&code, context, filter.clone(), Box::new(std::io::stderr())),
+        None if options.profile => {
+            let (result, profile) = profile(&code, context);
+            // The profile is printed even if the program failed
+            eprint!("{}", profile.report());
+            if let Some(file) = &options.stacks {
+                fs::write(file, profile.collapsed_stacks())
+                    .map_err(|e| LangError::new_io_error(e.to_string(), file.to_string()))?;
+            }
+            result
+        }
+        None => Interpreter::with_context(context).eval(&e),
+    };
+    println!("{}", result?);
+    Ok(())
+}
+
+/// Type checks the program without executing it
+/// Returns the exit code of the first type error
+fn check(source: &Source) -> Result<i32, LangError> {
+    let e = parse(&read_code(source)?)?;
+    let mut checker = Checker::new();
+    let errors = checker.check(&e);
+    for s in checker.signatures() {
+        println!("{}", s);
+    }
+    for e in errors.iter() {
+        eprintln!("{}", e);
+    }
+    if errors.is_empty() {
+        println!("No type error found");
+    } else {
+        eprintln!("{} type error(s) found", errors.len());
+    }
+    Ok(errors.first().map_or(SUCCESS, |e| e.exit_code()))
+}
+
+/// Prints the program formatted
+/// With `check`, prints nothing, and returns a failure if the program is not formatted
+fn fmt(source: &Source, check: bool, width: usize) -> Result<i32, LangError> {
+    let code = read_code(source)?;
+    let formatted = format(&code, width)?;
+    if !check {
+        print!("{}", formatted);
+        Ok(SUCCESS)
+    } else if formatted == code {
+        Ok(SUCCESS)
+    } else {
+        eprintln!("{} is not formatted", source.name());
+        Ok(FAILURE)
+    }
+}
+
+/// Prints the token tree of the program, as an S-expression or as JSON
+fn ast(source: &Source, json: bool) -> Result<(), LangError> {
+    let code = read_code(source)?;
+    let node = Node::token_tree(&code)?;
+    if json {
+        print!("{}", node.to_json(&code));
+    } else {
+        println!("{}", node.to_sexp());
+    }
+    Ok(())
+}
+
+/// Writes the text on the output of the interpreter, right away
+fn write_output(interpreter: &mut Interpreter, text: std::fmt::Arguments) -> Result<(), LangError> {
+    let output = interpreter.get_context().get_output();
+    output
+        .write_fmt(text)
+        .and_then(|_| output.flush())
+        .map_err(|e| LangError::new_io_error(e.to_string(), "repl".to_string()))
+}
+
+/// Reads and evaluates expressions until the end of the input
+/// Variables, functions and enums are kept by the interpreter from one expression to the next
+fn repl() -> Result<(), LangError> {
+    let mut interpreter = Interpreter::with_context(stdio_context(&[]));
     let mut code = String::new();
-    file.read_to_string(&mut code).unwrap();
+    loop {
+        // The prompt, the expressions and their values go through the streams of the context,
+        // shared with `(print ...)` and `(input)`
+        let prompt = if code.is_empty() { "> " } else { "... " };
+        write_output(&mut interpreter, format_args!("{}", prompt))?;
+        let mut line = String::new();
+        let read = interpreter
+            .get_context()
+            .get_input()
+            .read_line(&mut line)
+            .map_err(|e| LangError::new_io_error(e.to_string(), "repl".to_string()))?;
+        if read == 0 {
+            return write_output(&mut interpreter, format_args!("\n"));
+        }
+        code.push_str(&line);
+        if !is_complete(&code) {
+            continue;
+        }
+        let result = interpreter.eval_str(&code);
+        match result {
+            Ok(Expr::Empty) => {}
+            Ok(r) => write_output(&mut interpreter, format_args!("{}\n", r))?,
+            Err(e @ LangError::Exit(_)) => return Err(e),
+            Err(e) => eprintln!("{}", e),
+        }
+        code.clear();
+    }
+}
+
+/// Executes the program in the debugger, reading its commands from the standard input
+fn debug(source: &Source, args: &[String]) -> Result<(), LangError> {
+    let code = read_code(source)?;
+    let result = rusil::debugger::debug(&source.name(), &code, stdio_context(args))?;
+    println!("{}", result);
+    Ok(())
+}
+
+/// Returns the files `test_*.rsl` of the paths, in alphabetical order
+fn test_files(paths: &[String]) -> Result<Vec<String>, LangError> {
+    let mut files = Vec::new();
+    for path in paths {
+        if !Path::new(path).is_dir() {
+            files.push(path.to_string());
+            continue;
+        }
+        let entries = fs::read_dir(path)
+            .map_err(|e| LangError::new_io_error(e.to_string(), path.to_string()))?;
+        let mut tests: Vec<String> = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|p| {
+                let name = p.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+                name.starts_with("test_") && name.ends_with(".rsl")
+            })
+            .map(|p| p.display().to_string())
+            .collect();
+        tests.sort();
+        files.append(&mut tests);
+    }
+    Ok(files)
+}
+
+/// Executes every test file, and returns the exit code
+/// A test fails if it raises an error or exits with a non-zero status
+/// With `coverage`, prints the lines the tests covered, and with `lcov`, writes them to the file
+fn test(paths: &[String], coverage: bool, lcov: &Option<String>) -> Result<i32, LangError> {
+    let files = test_files(paths)?;
+    let mut failed = 0;
+    let mut covered = Vec::new();
+    for file in files.iter() {
+        println!("test {} ...", file);
+        let result = if coverage || lcov.is_some() {
+            let code = fs::read_to_string(file)
+                .map_err(|e| LangError::new_io_error(e.to_string(), file.to_string()));
+            code.and_then(|code| {
+                let (result, file_coverage) = cover(file, &code, stdio_context(&[]));
+                covered.push(file_coverage);
+                result
+            })
+        } else {
+            Interpreter::with_context(stdio_context(&[])).eval_file(file)
+        };
+        match result {
+            Ok(_) => println!("\n... ok"),
+            Err(e) if e.exit_code() == SUCCESS => println!("\n... ok"),
+            Err(e) => {
+                failed += 1;
+                println!("\n... FAILED");
+                eprintln!("{}", e);
+            }
+        }
+    }
+    println!(
+        "\ntest result: {} passed; {} failed",
+        files.len() - failed,
+        failed
+    );
+    if coverage {
+        print!("\n{}", report(&covered));
+    }
+    if let Some(file) = lcov {
+        fs::write(file, rusil::coverage::lcov(&covered))
+            .map_err(|e| LangError::new_io_error(e.to_string(), file.to_string()))?;
+    }
+    Ok(if failed == 0 { SUCCESS } else { FAILURE })
+}
 
-    let e = Expr::token_tree(code.as_str());
-    // println!("Tree: {:?}", e);
-    let result = e.exec();
+/// Executes the command, and returns the exit code of the process
+fn execute(command: &Command) -> i32 {
+    let result = match command {
+        Command::Help => {
+            print!("{}", USAGE);
+            Ok(SUCCESS)
+        }
+        Command::Run(source, args, options) => run(source, args, options).map(|_| SUCCESS),
+        Command::Check(source) => check(source),
+        Command::Fmt(source, check, width) => fmt(source, *check, *width),
+        Command::Ast(source, json) => ast(source, *json).map(|_| SUCCESS),
+        Command::Repl => repl().map(|_| SUCCESS),
+        Command::Test(paths, coverage, lcov) => test(paths, *coverage, lcov),
+        Command::Debug(source, args) => debug(source, args).map(|_| SUCCESS),
+    };
     match result {
-        Ok(r) => println!("{}", r),
-        Err(e) => println!("{}", e.description()),
+        Ok(code) => code,
+        // `(exit CODE)` stops the program without printing anything
+        Err(LangError::Exit(e)) => e.get_code(),
+        Err(e) => {
+            eprintln!("{}", e);
+            e.exit_code()
+        }
     }
 }
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let code = match Command::parse(&args) {
+        // The stack of the main thread is too small for deep recursions
+        Ok(command) => thread::Builder::new()
+            .stack_size(STACK_SIZE)
+            .spawn(move || execute(&command))
+            .and_then(|handle| handle.join().map_err(|_| std::io::ErrorKind::Other.into()))
+            .unwrap_or(FAILURE),
+        Err(message) => {
+            eprintln!("{}\n\n{}", message, USAGE);
+            FAILURE
+        }
+    };
+    process::exit(code);
+}
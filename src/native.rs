@@ -0,0 +1,346 @@
+use crate::errors::*;
+use crate::tokens::*;
+use crate::types::*;
+
+/// Conversion of a Rusil value into a Rust value, for the arguments of native functions
+pub trait FromRusil: Sized {
+    /// Returns the Rust value, or a TypeError about `expr_str` if the value has another type
+    fn from_rusil(value: Expr, expr_str: &str) -> Result<Self, LangError>;
+}
+
+/// Conversion of a Rust value into a Rusil value, for the results of native functions
+pub trait IntoRusil {
+    fn into_rusil(self) -> Result<Expr, LangError>;
+}
+
+impl FromRusil for Expr {
+    fn from_rusil(value: Expr, _expr_str: &str) -> Result<Self, LangError> {
+        Ok(value)
+    }
+}
+
+impl FromRusil for i32 {
+    fn from_rusil(value: Expr, expr_str: &str) -> Result<Self, LangError> {
+        value.get_num(expr_str.to_string())
+    }
+}
+
+impl FromRusil for bool {
+    fn from_rusil(value: Expr, expr_str: &str) -> Result<Self, LangError> {
+        value.get_bool(expr_str.to_string())
+    }
+}
+
+impl FromRusil for String {
+    fn from_rusil(value: Expr, expr_str: &str) -> Result<Self, LangError> {
+        value.get_str(expr_str.to_string())
+    }
+}
+
+impl<T: FromRusil> FromRusil for Vec<T> {
+    fn from_rusil(value: Expr, expr_str: &str) -> Result<Self, LangError> {
+        match value {
+            Expr::List(v) => v.into_iter().map(|e| T::from_rusil(e, expr_str)).collect(),
+            _ => Err(LangError::new_type_error(
+                Type::List,
+                value.get_type(),
+                expr_str.to_string(),
+            )),
+        }
+    }
+}
+
+/// `option.none` is `None`, and `(option.some VALUE)` is `Some(VALUE)`
+/// An item of `option` with other fields, built by the host, raises a ConversionError
+impl<T: FromRusil> FromRusil for Option<T> {
+    fn from_rusil(value: Expr, expr_str: &str) -> Result<Self, LangError> {
+        match value {
+            Expr::EnumValue {
+                enum_name,
+                variant,
+                mut fields,
+            } if enum_name == "option" => match (variant.as_str(), fields.len()) {
+                ("some", 1) => Ok(Some(T::from_rusil(fields.remove(0), expr_str)?)),
+                ("none", 0) => Ok(None),
+                _ => Err(LangError::new_conversion_error(format!(
+                    "Expected option.none or (option.some VALUE), found option.{} with {} field(s)",
+                    variant,
+                    fields.len()
+                ))),
+            },
+            _ => Err(LangError::new_type_error(
+                Type::Enum("option".to_string()),
+                value.get_type(),
+                expr_str.to_string(),
+            )),
+        }
+    }
+}
+
+impl IntoRusil for Expr {
+    fn into_rusil(self) -> Result<Expr, LangError> {
+        Ok(self)
+    }
+}
+
+impl IntoRusil for i32 {
+    fn into_rusil(self) -> Result<Expr, LangError> {
+        Ok(Expr::Number(self))
+    }
+}
+
+impl IntoRusil for bool {
+    fn into_rusil(self) -> Result<Expr, LangError> {
+        Ok(Expr::Bool(self))
+    }
+}
+
+impl IntoRusil for String {
+    fn into_rusil(self) -> Result<Expr, LangError> {
+        Ok(Expr::Str(self))
+    }
+}
+
+impl IntoRusil for &str {
+    fn into_rusil(self) -> Result<Expr, LangError> {
+        Ok(Expr::Str(self.to_string()))
+    }
+}
+
+impl IntoRusil for () {
+    fn into_rusil(self) -> Result<Expr, LangError> {
+        Ok(Expr::Empty)
+    }
+}
+
+impl<T: IntoRusil> IntoRusil for Vec<T> {
+    fn into_rusil(self) -> Result<Expr, LangError> {
+        Ok(Expr::List(
+            self.into_iter()
+                .map(|x| x.into_rusil())
+                .collect::<Result<Vec<Expr>, LangError>>()?,
+        ))
+    }
+}
+
+impl<T: IntoRusil> IntoRusil for Option<T> {
+    fn into_rusil(self) -> Result<Expr, LangError> {
+        Ok(Expr::option(self.map(|x| x.into_rusil()).transpose()?))
+    }
+}
+
+/// A native function can fail by returning an error
+impl<T: IntoRusil> IntoRusil for Result<T, LangError> {
+    fn into_rusil(self) -> Result<Expr, LangError> {
+        self?.into_rusil()
+    }
+}
+
+/// Rust closure that can be called from Rusil
+/// `Args` is the tuple of the types of its arguments
+pub trait NativeFunction<Args> {
+    fn arity(&self) -> usize;
+
+    /// Converts the arguments, calls the closure and converts its result
+    fn call(&self, args: Vec<Expr>, expr_str: &str) -> Result<Expr, LangError>;
+}
+
+/// Number of identifiers given to the macro
+macro_rules! count {
+    () => { 0 };
+    ($head:ident $(, $tail:ident)*) => { 1 + count!($($tail),*) };
+}
+
+macro_rules! impl_native_function {
+    ($($arg:ident),*) => {
+        impl<F, R, $($arg),*> NativeFunction<($($arg,)*)> for F
+        where
+            F: Fn($($arg),*) -> R,
+            R: IntoRusil,
+            $($arg: FromRusil),*
+        {
+            fn arity(&self) -> usize {
+                count!($($arg),*)
+            }
+
+            #[allow(non_snake_case, unused_mut, unused_variables)]
+            fn call(&self, args: Vec<Expr>, expr_str: &str) -> Result<Expr, LangError> {
+                let mut args = args.into_iter();
+                $(let $arg = $arg::from_rusil(args.next().unwrap_or(Expr::Empty), expr_str)?;)*
+                self($($arg),*).into_rusil()
+            }
+        }
+    };
+}
+
+/// Implements `NativeFunction` for the closures taking each number of arguments up to the
+/// number of identifiers given
+macro_rules! impl_native_functions {
+    () => {
+        impl_native_function!();
+    };
+    ($head:ident $(, $tail:ident)*) => {
+        impl_native_function!($head $(, $tail)*);
+        impl_native_functions!($($tail),*);
+    };
+}
+
+impl_native_functions!(A, B, C, D, E, G, H, I, J, K, L, M);
+
+/// Closure taking the evaluated arguments and the expression of the call
+type NativeClosure = dyn Fn(Vec<Expr>, &str) -> Result<Expr, LangError>;
+
+/// Native function registered in the interpreter
+pub(crate) struct Native {
+    name: String,
+    arity: usize,
+    function: Box<NativeClosure>,
+}
+
+impl Native {
+    pub(crate) fn new<Args, F: NativeFunction<Args> + 'static>(name: &str, f: F) -> Self {
+        Native {
+            name: name.to_string(),
+            arity: f.arity(),
+            function: Box::new(move |args, expr_str| f.call(args, expr_str)),
+        }
+    }
+
+    pub(crate) fn get_name(&self) -> String {
+        self.name.clone()
+    }
+
+    /// Calls the function with the evaluated arguments
+    /// If it is given a wrong number of arguments, returns an ArityError
+    pub(crate) fn call(&self, args: Vec<Expr>, expr_str: String) -> Result<Expr, LangError> {
+        if args.len() != self.arity {
+            return Err(LangError::new_arity_error(
+                self.name.to_string(),
+                self.arity,
+                Some(self.arity),
+                args.len(),
+                expr_str,
+            ));
+        }
+        (self.function)(args, &expr_str)
+    }
+}
+
+#[cfg(test)]
+mod tests_native {
+    use crate::interpreter::*;
+    use crate::native::*;
+
+    #[test]
+    fn test_register() {
+        let mut interpreter = Interpreter::new();
+        interpreter.register("add", |a: i32, b: i32| a + b);
+        interpreter.register("answer", || 42);
+        assert_eq!(
+            interpreter.eval_str("(call add (call answer) 1)").unwrap(),
+            Expr::Number(43)
+        );
+    }
+
+    #[test]
+    fn test_register_conversions() {
+        let mut interpreter = Interpreter::new();
+        interpreter.register("upper", |s: String| s.to_uppercase());
+        interpreter.register("total", |v: Vec<i32>| v.iter().sum::<i32>());
+        interpreter.register("first", |v: Vec<Expr>| v.into_iter().next());
+        assert_eq!(
+            interpreter.eval_str("(call upper \"abc\")").unwrap(),
+            Expr::Str("ABC".to_string())
+        );
+        assert_eq!(
+            interpreter.eval_str("(call total (list 1 2 3))").unwrap(),
+            Expr::Number(6)
+        );
+        assert_eq!(
+            interpreter
+                .eval_str("(match (call first (list)) ((option.some x) x) (option.none 0))")
+                .unwrap(),
+            Expr::Number(0)
+        );
+    }
+
+    #[test]
+    fn test_register_errors() {
+        let mut interpreter = Interpreter::new();
+        interpreter.register("add", |a: i32, b: i32| a + b);
+        interpreter.register("checked_div", |a: i32, b: i32| {
+            a.checked_div(b).ok_or_else(|| {
+                LangError::new_arithmetic_error("Division by zero".to_string(), String::new())
+            })
+        });
+        assert!(matches!(
+            interpreter.eval_str("(call add 1)"),
+            Err(LangError::ArityError(_))
+        ));
+        assert!(matches!(
+            interpreter.eval_str("(call add 1 \"a\")"),
+            Err(LangError::TypeError(_))
+        ));
+        assert!(matches!(
+            interpreter.eval_str("(call checked_div 1 0)"),
+            Err(LangError::ArithmeticError(_))
+        ));
+    }
+
+    #[test]
+    fn test_register_arity() {
+        let mut interpreter = Interpreter::new();
+        interpreter.register("sum", |a: i32, b: i32, c: i32, d: i32, e: i32, f: i32| {
+            a + b + c + d + e + f
+        });
+        assert_eq!(
+            interpreter.eval_str("(call sum 1 2 3 4 5 6)").unwrap(),
+            Expr::Number(21)
+        );
+        assert!(matches!(
+            interpreter.eval_str("(call sum 1 2 3 4 5)"),
+            Err(LangError::ArityError(_))
+        ));
+    }
+
+    #[test]
+    fn test_register_malformed_option() {
+        let mut interpreter = Interpreter::new();
+        interpreter.register("or_zero", |x: Option<i32>| x.unwrap_or(0));
+        let option = |variant: &str, fields: Vec<Expr>| Expr::EnumValue {
+            enum_name: "option".to_string(),
+            variant: variant.to_string(),
+            fields,
+        };
+        assert_eq!(
+            interpreter
+                .call_function("or_zero", vec![option("some", vec![Expr::Number(2)])])
+                .unwrap(),
+            Expr::Number(2)
+        );
+        assert_eq!(
+            interpreter
+                .call_function("or_zero", vec![option("none", vec![])])
+                .unwrap(),
+            Expr::Number(0)
+        );
+        for value in [
+            option("some", vec![]),
+            option("none", vec![Expr::Number(1)]),
+            option("other", vec![]),
+        ] {
+            assert!(matches!(
+                interpreter.call_function("or_zero", vec![value]),
+                Err(LangError::ConversionError(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_def_shadows_native() {
+        let mut interpreter = Interpreter::new();
+        interpreter.register("f", || 1);
+        interpreter.eval_str("(def f 2)").unwrap();
+        assert_eq!(interpreter.eval_str("(call f)").unwrap(), Expr::Number(2));
+    }
+}
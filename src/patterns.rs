@@ -0,0 +1,386 @@
+use crate::context::*;
+use crate::enums::*;
+use crate::errors::*;
+use crate::functions::*;
+use crate::reader::*;
+use crate::tokens::*;
+use std::collections::{HashMap, HashSet};
+
+/// Pattern of an arm of a `match` expression
+#[derive(Debug, PartialEq, Clone)]
+pub enum Pattern {
+    /// `_`: matches anything
+    Wildcard,
+    /// A number, a string, a boolean or an enum item: matches by equality
+    Literal(Expr),
+    /// A name: matches anything and binds the value to the name
+    Binding(String),
+    /// `(list P1 ... Pn)` or `(list P1 ... Pn & REST)`
+    List(Vec<Pattern>, Option<String>),
+    /// `(ENUM_NAME.ITEM P1 ... Pn)`: matches the item and destructures its fields
+    Variant(String, Vec<Pattern>),
+}
+
+impl Pattern {
+    /// Returns the pattern associated with the S-expression
+    pub(crate) fn token_tree(e: &Spanned) -> Result<Self, LangError> {
+        match e.atom() {
+            Some("_") => return Ok(Pattern::Wildcard),
+            Some(x) => {
+                return match Expr::from_atom(x)? {
+                    Expr::Var(name) => Ok(Pattern::Binding(name)),
+                    e => Ok(Pattern::Literal(e)),
+                }
+            }
+            None => {}
+        }
+        let items = e.get_items();
+        match items.first().and_then(Spanned::atom) {
+            Some("list") => {
+                let mut patterns = Vec::new();
+                let mut rest = None;
+                let mut iter = items.iter().skip(1);
+                while let Some(p) = iter.next() {
+                    if p.is_atom("&") {
+                        match iter.next() {
+                            Some(r) => rest = Some(r.get_atom("a name after &")?),
+                            None => {
+                                return Err(LangError::new_syntax_error(
+                                    "Expected a name after &".to_string(),
+                                    e.to_string(),
+                                ))
+                            }
+                        }
+                    } else {
+                        patterns.push(Pattern::token_tree(p)?);
+                    }
+                }
+                Ok(Pattern::List(patterns, rest))
+            }
+            Some(x) if is_enum_element(x) => Ok(Pattern::Variant(
+                x.to_string(),
+                items
+                    .iter()
+                    .skip(1)
+                    .map(Pattern::token_tree)
+                    .collect::<Result<Vec<Pattern>, LangError>>()?,
+            )),
+            _ => Ok(Pattern::Literal(Expr::from_sexp(e)?)),
+        }
+    }
+
+    /// Returns the pattern and the body of a `(PATTERN BODY)` arm
+    pub(crate) fn token_tree_arm(e: &Spanned) -> Result<(Self, Expr), LangError> {
+        match e.get_items() {
+            items if items.len() == 2 => {
+                Ok((Pattern::token_tree(&items[0])?, Expr::from_sexp(&items[1])?))
+            }
+            _ => Err(LangError::new_syntax_error(
+                "Expected an arm (PATTERN BODY)".to_string(),
+                e.to_string(),
+            )),
+        }
+    }
+
+    /// Returns true if the pattern matches everything
+    fn is_irrefutable(&self) -> bool {
+        matches!(self, Pattern::Wildcard | Pattern::Binding(_))
+    }
+
+    /// Returns true if the value matches the pattern
+    /// The names bound by the pattern are pushed into `bindings`
+    pub(crate) fn matches(
+        &self,
+        value: &Expr,
+        bindings: &mut Vec<(String, Expr)>,
+        variables: &mut HashMap<String, Expr>,
+        functions: &mut HashMap<String, Function>,
+        enums: &mut HashMap<String, Vec<Variant>>,
+        context: &mut Context,
+    ) -> Result<bool, LangError> {
+        match self {
+            Pattern::Wildcard => Ok(true),
+            Pattern::Binding(name) => {
+                bindings.push((name.to_string(), value.clone()));
+                Ok(true)
+            }
+            Pattern::Literal(e) => Ok(e.evaluate(variables, functions, enums, context)? == *value),
+            Pattern::List(patterns, rest) => {
+                let values = match value {
+                    Expr::List(values) => values,
+                    _ => return Ok(false),
+                };
+                if values.len() < patterns.len()
+                    || (rest.is_none() && values.len() != patterns.len())
+                {
+                    return Ok(false);
+                }
+                for (p, v) in patterns.iter().zip(values.iter()) {
+                    if !p.matches(v, bindings, variables, functions, enums, context)? {
+                        return Ok(false);
+                    }
+                }
+                if let Some(rest) = rest {
+                    let rest_values = Expr::List(values[patterns.len()..].to_vec());
+                    bindings.push((
+                        rest.to_string(),
+                        context.allocate(rest_values, &format!("& {}", rest))?,
+                    ));
+                }
+                Ok(true)
+            }
+            Pattern::Variant(name, patterns) => {
+                let (enum_name, variant_name) = name.split_once('.').unwrap_or((name, ""));
+                match value {
+                    Expr::EnumValue {
+                        enum_name: e,
+                        variant: v,
+                        fields,
+                    } if e == enum_name && v == variant_name && fields.len() == patterns.len() => {
+                        for (p, v) in patterns.iter().zip(fields.iter()) {
+                            if !p.matches(v, bindings, variables, functions, enums, context)? {
+                                return Ok(false);
+                            }
+                        }
+                        Ok(true)
+                    }
+                    _ => Ok(false),
+                }
+            }
+        }
+    }
+}
+
+/// Returns a warning for every `match` over an enum that does not cover all its items
+pub fn match_warnings(tree: &Expr) -> Vec<String> {
+    let mut enums: HashMap<String, Vec<String>> = builtin_enums()
+        .into_iter()
+        .map(|(name, items)| (name, items.iter().map(|v| v.get_name()).collect()))
+        .collect();
+    collect_enums(tree, &mut enums);
+    let mut warnings = Vec::new();
+    collect_match_warnings(tree, &enums, &mut warnings);
+    warnings
+}
+
+fn collect_enums(e: &Expr, enums: &mut HashMap<String, Vec<String>>) {
+    if let Expr::Enum(name, items, _) = e {
+        if let Expr::Var(name) = name.as_ref() {
+            let items = items.iter().map(|v| v.get_name()).collect();
+            enums.insert(name.to_string(), items);
+        }
+    }
+    e.children().iter().for_each(|c| collect_enums(c, enums));
+}
+
+fn collect_match_warnings(
+    e: &Expr,
+    enums: &HashMap<String, Vec<String>>,
+    warnings: &mut Vec<String>,
+) {
+    if let Expr::Match(_, arms, s) = e {
+        if !arms.iter().any(|(p, _)| p.is_irrefutable()) {
+            let covered: HashSet<&str> = arms
+                .iter()
+                .filter_map(|(p, _)| match p {
+                    Pattern::Literal(Expr::EnumElement(x)) => Some(x.as_str()),
+                    Pattern::Variant(x, patterns)
+                        if patterns.iter().all(|p| p.is_irrefutable()) =>
+                    {
+                        Some(x.as_str())
+                    }
+                    _ => None,
+                })
+                .collect();
+            let enum_name = covered
+                .iter()
+                .next()
+                .and_then(|x| x.split('.').next())
+                .and_then(|name| enums.get_key_value(name));
+            if let Some((name, items)) = enum_name {
+                let missing: Vec<String> = items
+                    .iter()
+                    .map(|i| format!("{}.{}", name, i))
+                    .filter(|i| !covered.contains(i.as_str()))
+                    .collect();
+                if !missing.is_empty() {
+                    warnings.push(format!(
+                        "\n>>> Warning: non-exhaustive match:\n\n\t\x1B[33m{}\x1B[39m\n\n\tMissing: {}\n",
+                        s,
+                        missing.join(", ")
+                    ));
+                }
+            }
+        }
+    }
+    e.children()
+        .iter()
+        .for_each(|c| collect_match_warnings(c, enums, warnings));
+}
+
+#[cfg(test)]
+mod tests_patterns {
+    use crate::patterns::*;
+
+    #[test]
+    fn test_match_number() {
+        assert_eq!(
+            Expr::token_tree("(match (+ 1 1) (1 10) (2 20) (_ 30))")
+                .unwrap()
+                .exec()
+                .unwrap(),
+            Expr::Number(20)
+        );
+    }
+
+    #[test]
+    fn test_match_wildcard() {
+        assert_eq!(
+            Expr::token_tree("(match 5 (1 10) (_ 30))")
+                .unwrap()
+                .exec()
+                .unwrap(),
+            Expr::Number(30)
+        );
+    }
+
+    #[test]
+    fn test_match_string() {
+        assert_eq!(
+            Expr::token_tree("(match \"b\" (\"a\" 1) (\"b\" 2))")
+                .unwrap()
+                .exec()
+                .unwrap(),
+            Expr::Number(2)
+        );
+    }
+
+    #[test]
+    fn test_match_enum() {
+        assert_eq!(
+            Expr::token_tree(
+                "((enum days mon tue wed) (match days.tue (days.mon 1) (days.tue 2) (days.wed 3)))"
+            )
+            .unwrap()
+            .exec()
+            .unwrap(),
+            Expr::Number(2)
+        );
+    }
+
+    #[test]
+    fn test_match_binding() {
+        assert_eq!(
+            Expr::token_tree("(match 4 (0 0) (n (* n n)))")
+                .unwrap()
+                .exec()
+                .unwrap(),
+            Expr::Number(16)
+        );
+    }
+
+    #[test]
+    fn test_match_binding_is_scoped() {
+        assert_eq!(
+            Expr::token_tree("((let n 1) (match 4 (n n)) n)")
+                .unwrap()
+                .exec()
+                .unwrap(),
+            Expr::Number(1)
+        );
+    }
+
+    #[test]
+    fn test_match_list() {
+        assert_eq!(
+            Expr::token_tree("(match (list 1 2 3) ((list a) a) ((list a b & r) (list b r)))")
+                .unwrap()
+                .exec()
+                .unwrap(),
+            Expr::List(vec![Expr::Number(2), Expr::List(vec![Expr::Number(3)])])
+        );
+    }
+
+    #[test]
+    fn test_match_no_arm() {
+        assert!(Expr::token_tree("(match 3 (1 1) (2 2))")
+            .unwrap()
+            .exec()
+            .is_err());
+    }
+
+    #[test]
+    fn test_match_arm_arity() {
+        for code in ["(match 3 (3 1 2))", "(match 3 (3))", "(match 3 ())"] {
+            assert!(
+                matches!(Expr::token_tree(code), Err(LangError::SyntaxError(_))),
+                "no error on: {}",
+                code
+            );
+        }
+    }
+
+    #[test]
+    fn test_match_warnings() {
+        let tree = Expr::token_tree(
+            "((enum days mon tue wed) (match days.tue (days.mon 1) (days.tue 2)))",
+        )
+        .unwrap();
+        let warnings = match_warnings(&tree);
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("days.wed"));
+        let tree =
+            Expr::token_tree("((enum days mon tue wed) (match days.tue (days.mon 1) (_ 2)))")
+                .unwrap();
+        assert!(match_warnings(&tree).is_empty());
+    }
+
+    #[test]
+    fn test_match_variant() {
+        assert_eq!(
+            Expr::token_tree(
+                "((enum shape (circle r) (rect w h)) (match (shape.rect 2 3) ((shape.circle r) (* 3 (* r r))) ((shape.rect w h) (* w h))))"
+            ).unwrap()
+            .exec()
+            .unwrap(),
+            Expr::Number(6)
+        );
+    }
+
+    #[test]
+    fn test_match_nested_variant() {
+        assert_eq!(
+            Expr::token_tree(
+                "((enum option none (some x)) (match (option.some (option.some 4)) ((option.some (option.some x)) x) (_ 0)))"
+            ).unwrap()
+            .exec()
+            .unwrap(),
+            Expr::Number(4)
+        );
+    }
+
+    #[test]
+    fn test_variant_arity() {
+        assert!(
+            Expr::token_tree("((enum shape (circle r)) (shape.circle 1 2))")
+                .unwrap()
+                .exec()
+                .is_err()
+        );
+        assert!(Expr::token_tree("((enum shape (circle r)) shape.circle)")
+            .unwrap()
+            .exec()
+            .is_err());
+    }
+
+    #[test]
+    fn test_match_warnings_variant() {
+        let tree = Expr::token_tree(
+            "((enum shape (circle r) (rect w h)) (match (shape.circle 1) ((shape.circle r) r)))",
+        )
+        .unwrap();
+        let warnings = match_warnings(&tree);
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("shape.rect"));
+    }
+}
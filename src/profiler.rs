@@ -0,0 +1,285 @@
+use crate::ast::SourceMap;
+use crate::context::*;
+use crate::enums::*;
+use crate::errors::*;
+use crate::functions::*;
+use crate::interpreter::*;
+use crate::tokens::*;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+/// Name of the frame of the whole program
+const PROGRAM: &str = "<program>";
+
+/// Measures of a function or a loop
+#[derive(Clone, Copy, Default)]
+struct Entry {
+    calls: u64,
+    /// Time spent in the function and in what it called
+    inclusive: Duration,
+    /// Time spent in the function itself
+    exclusive: Duration,
+}
+
+/// Function or loop being executed
+struct Frame {
+    name: String,
+    /// Where the frame ends: when fewer functions are being called, or when the evaluation
+    /// of the expression nested that deeply ends
+    end: End,
+    start: Instant,
+    /// Time spent in the frames it started
+    children: Duration,
+}
+
+/// Time and call counts of the functions and the loops of a program
+#[derive(Default)]
+pub struct Profile {
+    entries: HashMap<String, Entry>,
+    /// Time spent in each stack of frames itself, the stack being the names joined by `;`
+    stacks: HashMap<String, Duration>,
+    frames: Vec<Frame>,
+}
+
+/// Event ending a frame
+#[derive(Clone, Copy, PartialEq)]
+enum End {
+    /// The number of functions being called goes below this one
+    Return(usize),
+    /// The evaluation of the expression at this depth ends
+    Depth(usize),
+}
+
+impl Profile {
+    fn enter(&mut self, name: String, end: End) {
+        self.frames.push(Frame {
+            name,
+            end,
+            start: Instant::now(),
+            children: Duration::ZERO,
+        });
+    }
+
+    fn exit(&mut self) {
+        let frame = match self.frames.pop() {
+            Some(frame) => frame,
+            None => return,
+        };
+        let elapsed = frame.start.elapsed();
+        let exclusive = elapsed.saturating_sub(frame.children);
+        let stack = self
+            .frames
+            .iter()
+            .map(|f| f.name.as_str())
+            .chain(std::iter::once(frame.name.as_str()))
+            .collect::<Vec<&str>>()
+            .join(";");
+        *self.stacks.entry(stack).or_default() += exclusive;
+        // The time of a recursive call is already counted by the outermost one
+        let recursive = self.frames.iter().any(|f| f.name == frame.name);
+        let entry = self.entries.entry(frame.name).or_default();
+        entry.calls += 1;
+        entry.exclusive += exclusive;
+        if !recursive {
+            entry.inclusive += elapsed;
+        }
+        if let Some(parent) = self.frames.last_mut() {
+            parent.children += elapsed;
+        }
+    }
+
+    /// Returns the table of the functions and the loops, the longest inclusive time first
+    pub fn report(&self) -> String {
+        let mut entries: Vec<(&String, &Entry)> = self.entries.iter().collect();
+        entries.sort_by(|(n1, e1), (n2, e2)| e2.inclusive.cmp(&e1.inclusive).then(n1.cmp(n2)));
+        let mut out = format!(
+            "{:>10} {:>14} {:>14}  {}\n",
+            "calls", "inclusive ms", "exclusive ms", "name"
+        );
+        for (name, entry) in entries {
+            out.push_str(&format!(
+                "{:>10} {:>14.3} {:>14.3}  {}\n",
+                entry.calls,
+                entry.inclusive.as_secs_f64() * 1000.0,
+                entry.exclusive.as_secs_f64() * 1000.0,
+                name
+            ));
+        }
+        out
+    }
+
+    /// Returns the collapsed stacks read by flame graph tools, like `fibo;fibo 42`,
+    /// one per line with the time spent in the stack itself in microseconds
+    pub fn collapsed_stacks(&self) -> String {
+        let mut stacks: Vec<(&String, &Duration)> = self.stacks.iter().collect();
+        stacks.sort();
+        stacks
+            .iter()
+            .map(|(stack, time)| format!("{} {}\n", stack, time.as_micros()))
+            .collect()
+    }
+}
+
+/// Hook measuring the frames of the profile
+struct Profiler {
+    sources: SourceMap,
+    profile: Rc<RefCell<Profile>>,
+    /// Number of expressions being evaluated
+    depth: usize,
+}
+
+impl Profiler {
+    /// Returns the name of the frame starting with the expression, and where it ends
+    fn frame(&self, e: &Expr, context: &Context) -> Option<(String, End)> {
+        let profile = self.profile.borrow();
+        let calls = context.get_calls();
+        let line = || {
+            e.get_span()
+                .map_or("?".to_string(), |s| self.sources.line(s).to_string())
+        };
+        match profile.frames.last() {
+            None => Some((PROGRAM.to_string(), End::Depth(self.depth))),
+            // A function starts with the first expression evaluated once it is called,
+            // a default value or its core
+            Some(_) if calls.len() > profile.functions_called() => calls
+                .last()
+                .map(|name| (name.to_string(), End::Return(calls.len()))),
+            _ => match e {
+                Expr::For(..) => Some((format!("for:{}", line()), End::Depth(self.depth))),
+                Expr::While(..) => Some((format!("while:{}", line()), End::Depth(self.depth))),
+                _ => None,
+            },
+        }
+    }
+}
+
+impl Profile {
+    /// Returns the number of functions being called in the innermost function frame
+    fn functions_called(&self) -> usize {
+        self.frames
+            .iter()
+            .rev()
+            .find_map(|f| match f.end {
+                End::Return(n) => Some(n),
+                End::Depth(_) => None,
+            })
+            .unwrap_or(0)
+    }
+}
+
+impl Hook for Profiler {
+    fn before(
+        &mut self,
+        e: &Expr,
+        _variables: &mut HashMap<String, Expr>,
+        _functions: &mut HashMap<String, Function>,
+        _enums: &mut HashMap<String, Vec<Variant>>,
+        context: &mut Context,
+    ) -> Result<(), LangError> {
+        if let Some((name, end)) = self.frame(e, context) {
+            self.profile.borrow_mut().enter(name, end);
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
+    fn after(&mut self, _e: &Expr, _result: &Result<Expr, LangError>, context: &mut Context) {
+        self.depth -= 1;
+        let calls = context.get_calls().len();
+        let mut profile = self.profile.borrow_mut();
+        while profile.frames.last().is_some_and(|f| match f.end {
+            End::Return(n) => calls < n,
+            End::Depth(d) => self.depth == d,
+        }) {
+            profile.exit();
+        }
+    }
+}
+
+/// Executes the program, and returns its result and the profile of its execution
+/// Loops are named after their line, like `for:3` or `while:7`
+pub fn profile(code: &str, context: Context) -> (Result<Expr, LangError>, Profile) {
+    let profile = Rc::new(RefCell::new(Profile::default()));
+    let result = Expr::token_tree(code).and_then(|tree| {
+        let profiler = Profiler {
+            sources: SourceMap::new(code),
+            profile: profile.clone(),
+            depth: 0,
+        };
+        let mut interpreter = Interpreter::with_context(context);
+        interpreter.get_context().add_hook(Box::new(profiler));
+        interpreter.eval(&tree)
+    });
+    // The interpreter and its hooks are dropped
+    let profile = Rc::try_unwrap(profile).map_or_else(|p| p.take(), RefCell::into_inner);
+    (result, profile)
+}
+
+#[cfg(test)]
+mod tests_profiler {
+    use crate::profiler::*;
+    use std::io::Cursor;
+
+    const CODE: &str = "(def fibo n
+  (if (<= n 1) 1 (+ (call fibo (- n 1)) (call fibo (- n 2)))))
+(def sum n
+  ((let s 0)
+   (let i 1)
+   (while (<= i n)
+     ((set s (+ s (call fibo i)))
+      (set i (+ i 1))))
+   s))
+(call sum 4)";
+
+    fn profile_of(code: &str) -> (Result<Expr, LangError>, Profile) {
+        let context = Context::new(vec![], Box::new(Cursor::new("")), Box::new(Vec::new()));
+        profile(code, context)
+    }
+
+    #[test]
+    fn test_profile() {
+        let (result, profile) = profile_of(CODE);
+        assert_eq!(result.unwrap(), Expr::Number(1 + 2 + 3 + 5));
+        let calls = |name: &str| profile.entries.get(name).map(|e| e.calls);
+        assert_eq!(calls("<program>"), Some(1));
+        assert_eq!(calls("sum"), Some(1));
+        assert_eq!(calls("while:6"), Some(1));
+        // 1, 3, 5 and 9 calls for 1, 2, 3 and 4
+        assert_eq!(calls("fibo"), Some(18));
+        let total = profile.entries["<program>"].inclusive;
+        assert!(profile.entries["fibo"].inclusive <= total);
+        assert!(profile.entries.values().all(|e| e.exclusive <= e.inclusive));
+        assert!(profile
+            .report()
+            .lines()
+            .nth(1)
+            .is_some_and(|l| l.ends_with("  <program>")));
+    }
+
+    #[test]
+    fn test_collapsed_stacks() {
+        let (_, profile) = profile_of(CODE);
+        let stacks = profile.collapsed_stacks();
+        let stacks: Vec<&str> = stacks
+            .lines()
+            .map(|l| l.rsplit_once(' ').unwrap().0)
+            .collect::<Vec<&str>>();
+        assert_eq!(
+            stacks,
+            vec![
+                "<program>",
+                "<program>;sum",
+                "<program>;sum;while:6",
+                "<program>;sum;while:6;fibo",
+                "<program>;sum;while:6;fibo;fibo",
+                "<program>;sum;while:6;fibo;fibo;fibo",
+                "<program>;sum;while:6;fibo;fibo;fibo;fibo",
+            ]
+        );
+        let (result, profile) = profile_of("(call fibo 1)");
+        assert!(result.is_err());
+        assert_eq!(profile.collapsed_stacks().lines().count(), 1);
+    }
+}
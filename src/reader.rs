@@ -0,0 +1,326 @@
+use crate::errors::*;
+use std::fmt;
+
+/// Nesting depth after which the source is rejected, so that turning it into a token tree
+/// cannot overflow the stack of a thread spawned by the standard library
+/// Its evaluation is limited by the stack size allowed by the `Context`
+const MAX_DEPTH: usize = 128;
+
+/// S-expression read from the source, before being turned into an `Expr`
+#[derive(Debug, PartialEq, Clone)]
+pub enum SExp {
+    /// A name, a number, a quoted string, ...
+    Atom(String),
+    /// `(E1 E2 ... En)`
+    List(Vec<SExp>),
+}
+
+impl fmt::Display for SExp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SExp::Atom(x) => write!(f, "{}", x),
+            SExp::List(v) => write!(
+                f,
+                "({})",
+                v.iter()
+                    .map(|e| e.to_string())
+                    .collect::<Vec<String>>()
+                    .join(" ")
+            ),
+        }
+    }
+}
+
+/// Byte offsets of the first character of an expression in the source, and of the following one
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+/// S-expression read from the source, with its position
+#[derive(Debug, PartialEq, Clone)]
+pub struct Spanned {
+    /// Byte offset of its first character
+    pub start: usize,
+    /// Byte offset following its last character
+    pub end: usize,
+    /// Text of an atom, or None for a list
+    atom: Option<String>,
+    items: Vec<Spanned>,
+}
+
+impl Spanned {
+    /// Returns the items of a list, or nothing for an atom
+    pub fn get_items(&self) -> &[Spanned] {
+        &self.items
+    }
+
+    /// Returns the text of an atom, or nothing for a list
+    pub(crate) fn atom(&self) -> Option<&str> {
+        self.atom.as_deref()
+    }
+
+    /// Returns true if the S-expression is the given atom
+    pub(crate) fn is_atom(&self, x: &str) -> bool {
+        self.atom() == Some(x)
+    }
+
+    /// Returns the text of the atom, or a SyntaxError naming what was expected
+    pub(crate) fn get_atom(&self, expected: &str) -> Result<String, LangError> {
+        match self.atom() {
+            Some(x) => Ok(x.to_string()),
+            None => Err(LangError::new_syntax_error(
+                format!("Expected {}", expected),
+                self.to_string(),
+            )),
+        }
+    }
+
+    pub fn get_span(&self) -> Span {
+        Span {
+            start: self.start,
+            end: self.end,
+        }
+    }
+
+    /// Returns the S-expression, without the positions
+    pub fn to_sexp(&self) -> SExp {
+        match &self.atom {
+            Some(x) => SExp::Atom(x.to_string()),
+            None => SExp::List(self.items.iter().map(|e| e.to_sexp()).collect()),
+        }
+    }
+}
+
+impl fmt::Display for Spanned {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.atom {
+            Some(x) => write!(f, "{}", x),
+            None => write!(
+                f,
+                "({})",
+                self.items
+                    .iter()
+                    .map(|e| e.to_string())
+                    .collect::<Vec<String>>()
+                    .join(" ")
+            ),
+        }
+    }
+}
+
+/// Returns the line and the column, starting from 1, of the given character position
+pub fn line_column(s: &str, index: usize) -> (usize, usize) {
+    let before = &s[..index];
+    let line = before.matches('\n').count() + 1;
+    let column = before.chars().rev().take_while(|c| *c != '\n').count() + 1;
+    (line, column)
+}
+
+/// Returns "line L, column C" for the given character position
+fn position(s: &str, index: usize) -> String {
+    let (line, column) = line_column(s, index);
+    format!("line {}, column {}", line, column)
+}
+
+/// Reads all the S-expressions of the source
+pub fn read(s: &str) -> Result<Vec<SExp>, LangError> {
+    Ok(read_spanned(s)?.iter().map(|e| e.to_sexp()).collect())
+}
+
+/// Reads all the S-expressions of the source, with their positions
+pub fn read_spanned(s: &str) -> Result<Vec<Spanned>, LangError> {
+    Ok(read_with_comments(s)?.0)
+}
+
+/// Reads all the S-expressions of the source, with their positions,
+/// and the positions of the comments, in the order of the source
+pub(crate) fn read_with_comments(s: &str) -> Result<(Vec<Spanned>, Vec<Span>), LangError> {
+    // Lists being read, with the position of their opening parenthesis
+    let mut stack: Vec<(Vec<Spanned>, usize)> = vec![(Vec::new(), 0)];
+    let mut comments = Vec::new();
+    let mut chars = s.char_indices().peekable();
+
+    while let Some((i, c)) = chars.next() {
+        if c.is_whitespace() {
+            continue;
+        } else if c == ';' {
+            // A comment goes until the end of the line
+            let mut end = i + 1;
+            while let Some((j, c)) = chars.next_if(|(_, c)| *c != '\n') {
+                end = j + c.len_utf8();
+            }
+            comments.push(Span { start: i, end });
+        } else if c == '(' {
+            if stack.len() > MAX_DEPTH {
+                return Err(LangError::new_syntax_error(
+                    format!("Expression nested too deeply at {}", position(s, i)),
+                    String::new(),
+                )
+                .with_span(Some(Span {
+                    start: i,
+                    end: i + 1,
+                })));
+            }
+            stack.push((Vec::new(), i));
+        } else if c == ')' {
+            if stack.len() == 1 {
+                return Err(LangError::new_syntax_error(
+                    format!("Unexpected ')' at {}", position(s, i)),
+                    String::new(),
+                )
+                .with_span(Some(Span {
+                    start: i,
+                    end: i + 1,
+                })));
+            }
+            let (items, start) = stack.pop().unwrap_or_default();
+            if let Some((parent, _)) = stack.last_mut() {
+                parent.push(Spanned {
+                    start,
+                    end: i + 1,
+                    atom: None,
+                    items,
+                });
+            }
+        } else {
+            let mut atom = String::from(c);
+            if c == '"' {
+                // A string goes until the next quote, and can contain spaces and parentheses
+                let mut closed = false;
+                for (_, c) in chars.by_ref() {
+                    atom.push(c);
+                    if c == '"' {
+                        closed = true;
+                        break;
+                    }
+                }
+                if !closed {
+                    return Err(LangError::new_syntax_error(
+                        format!("Unterminated string starting at {}", position(s, i)),
+                        atom,
+                    )
+                    .with_span(Some(Span {
+                        start: i,
+                        end: s.len(),
+                    })));
+                }
+            } else {
+                while let Some((_, c)) = chars.peek() {
+                    if c.is_whitespace() || *c == '(' || *c == ')' || *c == ';' {
+                        break;
+                    }
+                    atom.push(*c);
+                    chars.next();
+                }
+            }
+            if let Some((parent, _)) = stack.last_mut() {
+                parent.push(Spanned {
+                    start: i,
+                    end: i + atom.len(),
+                    atom: Some(atom),
+                    items: vec![],
+                });
+            }
+        }
+    }
+
+    if stack.len() > 1 {
+        let (_, start) = stack[stack.len() - 1];
+        return Err(LangError::new_syntax_error(
+            format!("Missing ')' for the '(' at {}", position(s, start)),
+            String::new(),
+        )
+        .with_span(Some(Span {
+            start,
+            end: start + 1,
+        })));
+    }
+    Ok((stack.pop().map(|(v, _)| v).unwrap_or_default(), comments))
+}
+
+/// Returns false if the source ends inside a list or a string, so more lines are expected
+pub fn is_complete(s: &str) -> bool {
+    let mut depth = 0;
+    let mut in_string = false;
+    let mut in_comment = false;
+    for c in s.chars() {
+        match c {
+            '\n' => in_comment = false,
+            _ if in_comment => {}
+            ';' if !in_string => in_comment = true,
+            '"' => in_string = !in_string,
+            '(' if !in_string => depth += 1,
+            ')' if !in_string => depth -= 1,
+            _ => {}
+        }
+    }
+    depth <= 0 && !in_string
+}
+
+#[cfg(test)]
+mod tests_reader {
+    use crate::reader::*;
+
+    #[test]
+    fn test_read() {
+        assert_eq!(
+            read("(+ 1 (f \"a (b\"))").unwrap(),
+            vec![SExp::List(vec![
+                SExp::Atom("+".to_string()),
+                SExp::Atom("1".to_string()),
+                SExp::List(vec![
+                    SExp::Atom("f".to_string()),
+                    SExp::Atom("\"a (b\"".to_string())
+                ])
+            ])]
+        );
+    }
+
+    #[test]
+    fn test_read_comments() {
+        assert_eq!(
+            read("; start\n(print \"a;b\"); end\n2;3").unwrap(),
+            vec![
+                SExp::List(vec![
+                    SExp::Atom("print".to_string()),
+                    SExp::Atom("\"a;b\"".to_string())
+                ]),
+                SExp::Atom("2".to_string())
+            ]
+        );
+        let code = "; start\n(print \"a;b\"); é\n2;3";
+        let (_, comments) = read_with_comments(code).unwrap();
+        let texts: Vec<&str> = comments.iter().map(|c| &code[c.start..c.end]).collect();
+        assert_eq!(texts, vec!["; start", "; é", ";3"]);
+    }
+
+    #[test]
+    fn test_read_spanned() {
+        let code = "(+ 1\n  \"é\")";
+        let e = &read_spanned(code).unwrap()[0];
+        assert_eq!((e.start, e.end), (0, code.len()));
+        let s = &e.get_items()[2];
+        assert_eq!(&code[s.start..s.end], "\"é\"");
+        assert_eq!(line_column(code, s.start), (2, 3));
+    }
+
+    #[test]
+    fn test_read_errors() {
+        assert!(read("(+ 1 2").is_err());
+        assert!(read("(+ 1 2))").is_err());
+        assert!(read("(print \"abc)").is_err());
+        assert!(read(&"(".repeat(1000)).is_err());
+    }
+
+    #[test]
+    fn test_is_complete() {
+        assert!(is_complete("(+ 1 2)"));
+        assert!(is_complete("(+ 1 2))"));
+        assert!(!is_complete("(print \"a)\""));
+        assert!(!is_complete("(print \"a)"));
+        assert!(!is_complete("(print 1 ; a)"));
+        assert!(is_complete("(print \"a;\") ; (\n"));
+    }
+}
@@ -0,0 +1,227 @@
+//! Fuzz-style tests: no source text can make the parser or the evaluator panic
+
+use crate::checker::*;
+use crate::context::*;
+use crate::errors::*;
+use crate::interpreter::*;
+use crate::patterns::*;
+use crate::tokens::*;
+use std::io::Cursor;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+/// Number of random programs generated by each test
+const CASES: usize = 2000;
+/// Number of expressions each program can evaluate, so that loops end
+const FUEL: u64 = 10_000;
+
+/// Deterministic pseudo-random generator (xorshift), so that failures can be replayed
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    fn pick<'a>(&mut self, v: &[&'a str]) -> &'a str {
+        v[self.below(v.len())]
+    }
+}
+
+/// Atoms used to build programs
+const ATOMS: &[&str] = &[
+    "0",
+    "1",
+    "-1",
+    "2",
+    "7",
+    "2147483647",
+    "-2147483648",
+    "99999999999",
+    "1.5",
+    "x",
+    "y",
+    "f",
+    "_",
+    "&",
+    ":",
+    "->",
+    "int",
+    "str",
+    "\"a\"",
+    "\"\"",
+    "\"(\"",
+    "true",
+    "false",
+    "'red",
+    "'",
+    "e",
+    "e.a",
+    "e.b",
+    "e.",
+    ".a",
+    "list",
+    "(input)",
+];
+
+/// Keywords used to build programs
+const KEYWORDS: &[&str] = &[
+    "+", "-", "*", "/", "%", "=", "!=", ">", ">=", "<", "<=", "&&", "||", "!", "if", "let", "set",
+    "def", "call", "enum", "for", "while", "match", "variants", "list", "print", "input",
+];
+
+/// Returns a random sequence of characters and tokens, mostly not well-formed
+fn soup(rng: &mut Rng) -> String {
+    let mut s = String::new();
+    for _ in 0..rng.below(40) {
+        match rng.below(4) {
+            0 => s.push('('),
+            1 => s.push(')'),
+            2 => s.push_str(rng.pick(ATOMS)),
+            _ => s.push_str(rng.pick(KEYWORDS)),
+        }
+        if rng.below(2) == 0 {
+            s.push(' ');
+        }
+    }
+    s
+}
+
+/// Returns a random well-formed S-expression
+/// Functions never call anything, so that no program makes exponentially many calls
+fn sexp(rng: &mut Rng, depth: usize, in_def: bool) -> String {
+    if depth == 0 || rng.below(3) == 0 {
+        return rng.pick(ATOMS).to_string();
+    }
+    let keyword = rng.pick(KEYWORDS);
+    match keyword {
+        "call" if in_def => rng.pick(ATOMS).to_string(),
+        "for" => format!(
+            "(for x {} {} {})",
+            rng.below(4),
+            rng.below(4),
+            sexp(rng, depth - 1, in_def)
+        ),
+        "def" => format!(
+            "(def f {} {})",
+            (0..rng.below(3))
+                .map(|_| rng.pick(&["x", "y", "(x 1)", "(y : int)", "& y"]))
+                .collect::<Vec<&str>>()
+                .join(" "),
+            sexp(rng, depth - 1, true)
+        ),
+        _ => {
+            let args: Vec<String> = (0..rng.below(5))
+                .map(|_| sexp(rng, depth - 1, in_def))
+                .collect();
+            format!("({} {})", keyword, args.join(" "))
+        }
+    }
+}
+
+/// Executes the program with a limited fuel, reading lines from a fixed input
+fn exec(e: &Expr) -> Result<Expr, LangError> {
+    let input = Box::new(Cursor::new("1\na\n"));
+    let mut interpreter = Interpreter::with_context(Context::new(vec![], input, Box::new(vec![])));
+    interpreter.set_fuel(Some(FUEL));
+    interpreter.eval(e)
+}
+
+/// Parses, checks and executes the source, and fails if anything panicked
+fn assert_no_panic(s: &str) {
+    let result = catch_unwind(AssertUnwindSafe(|| {
+        if let Ok(e) = Expr::token_tree(s) {
+            let _ = match_warnings(&e);
+            let _ = Checker::new().check(&e);
+            let _ = exec(&e);
+        }
+    }));
+    assert!(result.is_ok(), "panicked on: {}", s);
+}
+
+#[test]
+fn test_fuzz_soup() {
+    let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+    for _ in 0..CASES {
+        assert_no_panic(&soup(&mut rng));
+    }
+}
+
+#[test]
+fn test_fuzz_well_formed() {
+    let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+    for _ in 0..CASES {
+        let program: Vec<String> = (0..1 + rng.below(4))
+            .map(|_| sexp(&mut rng, 5, false))
+            .collect();
+        assert_no_panic(&format!("({})", program.join(" ")));
+    }
+}
+
+#[test]
+fn test_fuzz_edge_cases() {
+    for s in [
+        "",
+        "(",
+        ")",
+        "()",
+        "(())",
+        "\"",
+        "'",
+        "(let)",
+        "(def)",
+        "(def f ->)",
+        "(def f & )",
+        "(match)",
+        "(match 1 ())",
+        "(match 1 ((list &) 1))",
+        "(enum)",
+        "(enum e ())",
+        "(call)",
+        "(call 1)",
+        "(for)",
+        "(for 1 2 3 4)",
+        "(e.a)",
+        "(variants 1)",
+    ] {
+        assert_no_panic(s);
+    }
+    assert_no_panic(&"(".repeat(100_000));
+    assert_no_panic(&format!("{}1{}", "(! ".repeat(128), ")".repeat(128)));
+    assert_no_panic(&format!("{}1{}", "(! ".repeat(129), ")".repeat(129)));
+    for (open, close, n) in [
+        ("(+ 1 ", ")", 128),
+        ("(if true ", " 0)", 128),
+        ("(let x ", ")", 128),
+        ("(list ", ")", 128),
+        ("(print ", ")", 128),
+        ("(match 1 (x ", "))", 64),
+        ("(def f x ", ")", 128),
+    ] {
+        assert_no_panic(&format!("{}1{}", open.repeat(n), close.repeat(n)));
+    }
+}
+
+#[test]
+fn test_fuzz_recursion() {
+    for s in [
+        "((def f x (call f x)) (call f 1))",
+        "((def f x (+ 1 (if true (! (call f x)) 0))) (call f 1))",
+        "((def f (x 0) (call g x)) (def g x (call f x)) (call f))",
+        "((def f & x (call f 1 x)) (call f))",
+    ] {
+        assert_no_panic(s);
+        let result = Expr::token_tree(s).and_then(|e| exec(&e));
+        assert!(
+            matches!(result, Err(LangError::ResourceExhausted(_))),
+            "no error on: {}",
+            s
+        );
+    }
+}
@@ -1,46 +1,122 @@
+use crate::capabilities::*;
+use crate::context::*;
+use crate::enums::*;
 use crate::errors::*; // type_errors::TypeError;
 use crate::functions::*;
+use crate::patterns::*;
+use crate::reader::*;
 use crate::types::*;
-use std;
-use std::collections::{HashMap, HashSet};
+use std::collections::HashMap;
 use std::fmt;
-use std::io::{prelude, Write};
-use std::io::{stdin, stdout};
-use std::ops::{Add, Div, Mul, Not, Sub};
+use std::ops::Not;
+use std::rc::Rc;
+
+/// Text of an expression read from the code, with its position and the ones of its
+/// sub-expressions, so that names and literals can be found too
+/// Expressions built by the interpreter have no position
+#[derive(Debug, Clone, Default)]
+pub struct Source {
+    text: String,
+    span: Option<Span>,
+    /// Spans of the sub-expressions, in the order of `Expr::children`
+    children: Vec<Span>,
+}
+
+impl Source {
+    pub(crate) fn new(text: String, span: Span, children: Vec<Span>) -> Self {
+        Source {
+            text,
+            span: Some(span),
+            children,
+        }
+    }
+
+    pub fn get_span(&self) -> Option<Span> {
+        self.span
+    }
+
+    /// Returns the span of the i-th sub-expression, in the order of `Expr::children`
+    pub fn get_child_span(&self, i: usize) -> Option<Span> {
+        self.children.get(i).copied()
+    }
+}
+
+impl From<String> for Source {
+    fn from(text: String) -> Self {
+        Source {
+            text,
+            ..Source::default()
+        }
+    }
+}
+
+/// Two expressions are equal if they have the same text, wherever they are
+impl PartialEq for Source {
+    fn eq(&self, other: &Self) -> bool {
+        self.text == other.text
+    }
+}
+
+impl std::ops::Deref for Source {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.text
+    }
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
 
 #[derive(Debug, PartialEq, Clone)]
-pub(crate) enum Expr {
+pub enum Expr {
     Number(i32),
     Bool(bool),
     Var(String),
+    Symbol(String),
     Str(String),
     EnumElement(String),
+    EnumValue {
+        enum_name: String,
+        variant: String,
+        fields: Vec<Expr>,
+    },
     // ------------------------------
-    Add(Box<Expr>, Box<Expr>, String),
-    Sub(Box<Expr>, Box<Expr>, String),
-    Mul(Box<Expr>, Box<Expr>, String),
-    Div(Box<Expr>, Box<Expr>, String),
-    Mod(Box<Expr>, Box<Expr>, String),
-    Equal(Box<Expr>, Box<Expr>, String),
-    GreaterThan(Box<Expr>, Box<Expr>, String),
-    GreaterEqualThan(Box<Expr>, Box<Expr>, String),
-    LessThan(Box<Expr>, Box<Expr>, String),
-    LessEqualThan(Box<Expr>, Box<Expr>, String),
-    NEqual(Box<Expr>, Box<Expr>, String),
-    And(Box<Expr>, Box<Expr>, String),
-    Or(Box<Expr>, Box<Expr>, String),
-    Not(Box<Expr>, String),
-    If(Box<Expr>, Box<Expr>, Box<Expr>, String),
-    Let(Box<Expr>, Box<Expr>, String),
-    Set(Box<Expr>, Box<Expr>, String),
-    Sequence(Vec<Box<Expr>>, String),
-    For(Box<Expr>, Box<Expr>, Box<Expr>, Box<Expr>, String),
-    While(Box<Expr>, Box<Expr>, String),
-    Define(Box<Expr>, Vec<Box<Expr>>, Box<Expr>, String),
-    Call(Box<Expr>, Vec<Box<Expr>>, String),
-    Print(Vec<Box<Expr>>),
-    Enum(Box<Expr>, Vec<Box<Expr>>, String),
+    Add(Box<Expr>, Box<Expr>, Source),
+    Sub(Box<Expr>, Box<Expr>, Source),
+    Mul(Box<Expr>, Box<Expr>, Source),
+    Div(Box<Expr>, Box<Expr>, Source),
+    Mod(Box<Expr>, Box<Expr>, Source),
+    Equal(Box<Expr>, Box<Expr>, Source),
+    GreaterThan(Box<Expr>, Box<Expr>, Source),
+    GreaterEqualThan(Box<Expr>, Box<Expr>, Source),
+    LessThan(Box<Expr>, Box<Expr>, Source),
+    LessEqualThan(Box<Expr>, Box<Expr>, Source),
+    NEqual(Box<Expr>, Box<Expr>, Source),
+    And(Box<Expr>, Box<Expr>, Source),
+    Or(Box<Expr>, Box<Expr>, Source),
+    Not(Box<Expr>, Source),
+    If(Box<Expr>, Box<Expr>, Box<Expr>, Source),
+    Let(Box<Expr>, Option<Type>, Box<Expr>, Source),
+    Set(Box<Expr>, Box<Expr>, Source),
+    Sequence(Vec<Expr>, Source),
+    For(Box<Expr>, Box<Expr>, Box<Expr>, Box<Expr>, Source),
+    While(Box<Expr>, Box<Expr>, Source),
+    Define(Box<Expr>, Vec<Param>, Option<Type>, Rc<Expr>, Source),
+    Call(Box<Expr>, Vec<Expr>, Source),
+    Print(Vec<Expr>, Source),
+    Enum(Box<Expr>, Vec<Variant>, Source),
+    EnumConstruct(String, Vec<Expr>, Source),
+    Variants(Box<Expr>, Source),
+    Match(Box<Expr>, Vec<(Pattern, Expr)>, Source),
+    List(Vec<Expr>),
     Input,
+    Args,
+    Env(Box<Expr>, Source),
+    Exit(Box<Expr>, Source),
     Empty,
 }
 
@@ -50,6 +126,35 @@ impl fmt::Display for Expr {
             Expr::Number(x) => write!(f, "{}", x),
             Expr::Bool(x) => write!(f, "{}", x),
             Expr::Str(x) => write!(f, "{}", x),
+            Expr::Symbol(x) => write!(f, "'{}", x),
+            Expr::List(v) => write!(
+                f,
+                "[{}]",
+                v.iter()
+                    .map(|e| e.to_string())
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ),
+            Expr::EnumValue {
+                enum_name,
+                variant,
+                fields,
+            } if fields.is_empty() => write!(f, "{}.{}", enum_name, variant),
+            Expr::EnumValue {
+                enum_name,
+                variant,
+                fields,
+            } => write!(
+                f,
+                "({}.{} {})",
+                enum_name,
+                variant,
+                fields
+                    .iter()
+                    .map(|e| e.to_string())
+                    .collect::<Vec<String>>()
+                    .join(" ")
+            ),
             _ => write!(f, ""),
         }
     }
@@ -73,18 +178,21 @@ impl Not for Expr {
 
 impl Expr {
     /// Returns the type of the expression
-    fn get_type(&self) -> Type {
-        match *self {
+    pub(crate) fn get_type(&self) -> Type {
+        match self {
             Expr::Number(_) => Type::Number,
             Expr::Str(_) => Type::Str,
             Expr::Var(_) => Type::Var,
+            Expr::Symbol(_) => Type::Symbol,
             Expr::Bool(_) => Type::Bool,
+            Expr::List(_) => Type::List,
+            Expr::EnumValue { enum_name, .. } => Type::Enum(enum_name.to_string()),
             _ => Type::Expression,
         }
     }
     /// Returns the number encapsulated in the expression
     /// If it is not a number, returns a TypeError
-    fn get_num(&self, expr_str: String) -> Result<i32, LangError> {
+    pub(crate) fn get_num(&self, expr_str: String) -> Result<i32, LangError> {
         if let Expr::Number(x) = self {
             Ok(*x)
         } else {
@@ -95,7 +203,7 @@ impl Expr {
             ))
         }
     }
-    fn get_bool(&self, expr_str: String) -> Result<bool, LangError> {
+    pub(crate) fn get_bool(&self, expr_str: String) -> Result<bool, LangError> {
         if let Expr::Bool(x) = self {
             Ok(*x)
         } else {
@@ -109,7 +217,7 @@ impl Expr {
 
     /// Returns the string encapsulated in the expression
     /// If it is not a string, returns a TypeError
-    fn get_str(&self, expr_str: String) -> Result<String, LangError> {
+    pub(crate) fn get_str(&self, expr_str: String) -> Result<String, LangError> {
         if let Expr::Str(x) = self {
             Ok(x.to_string())
         } else {
@@ -121,9 +229,28 @@ impl Expr {
         }
     }
 
+    /// Returns the approximate number of bytes taken by the value
+    pub(crate) fn size(&self) -> usize {
+        let own = std::mem::size_of::<Expr>();
+        match self {
+            Expr::Str(x) | Expr::Var(x) | Expr::Symbol(x) | Expr::EnumElement(x) => own + x.len(),
+            Expr::List(v) => own + v.iter().map(|e| e.size()).sum::<usize>(),
+            Expr::EnumValue {
+                enum_name,
+                variant,
+                fields,
+            } => {
+                own + enum_name.len()
+                    + variant.len()
+                    + fields.iter().map(|e| e.size()).sum::<usize>()
+            }
+            _ => own,
+        }
+    }
+
     /// Returns the string encapsulated in the expression
     /// If it is not a string, returns a TypeError
-    fn get_var(&self, expr_str: String) -> Result<String, LangError> {
+    pub(crate) fn get_var(&self, expr_str: String) -> Result<String, LangError> {
         if let Expr::Var(x) = self {
             Ok(x.to_string())
         } else {
@@ -135,9 +262,38 @@ impl Expr {
         }
     }
 
+    /// Returns a TypeError if there is a type annotation and the value does not have this type
+    fn check_type(&self, annotation: &Option<Type>, expr_str: String) -> Result<(), LangError> {
+        match annotation {
+            Some(t) if *t != self.get_type() => Err(LangError::new_type_error(
+                t.clone(),
+                self.get_type(),
+                expr_str,
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns true if the two values are equal
+    /// If they are not of the same type, returns a TypeError
+    fn equals(&self, other: &Expr, expr_str: String) -> Result<bool, LangError> {
+        if self.get_type() != other.get_type() {
+            return Err(LangError::new_type_error(
+                self.get_type(),
+                other.get_type(),
+                expr_str,
+            ));
+        }
+        Ok(self == other)
+    }
+
     /// Takes 2 Numbers and returns the number op(x, y)
     /// If type error returns a TypeError
-    fn arith_operation<T, FOP: Fn(T, T) -> Expr, FGET: Fn(Expr) -> Result<T, LangError>>(
+    fn arith_operation<
+        T,
+        FOP: Fn(T, T) -> Result<Expr, LangError>,
+        FGET: Fn(Expr) -> Result<T, LangError>,
+    >(
         self,
         other: Expr,
         op: FOP,
@@ -145,522 +301,1289 @@ impl Expr {
     ) -> Result<Expr, LangError> {
         let x = get_f(self)?;
         let y = get_f(other)?;
-        Ok(op(x, y))
+        op(x, y)
+    }
+
+    /// Returns the result of a checked operation on numbers
+    /// If it overflowed, returns an ArithmeticError
+    fn checked(result: Option<i32>, expr_str: &str) -> Result<Expr, LangError> {
+        result.map(Expr::Number).ok_or_else(|| {
+            LangError::new_arithmetic_error(
+                format!(
+                    "Integer overflow (integers go from {} to {})",
+                    i32::MIN,
+                    i32::MAX
+                ),
+                expr_str.to_string(),
+            )
+        })
+    }
+
+    /// Returns the divisor, or an ArithmeticError if it is zero
+    fn non_zero(divisor: i32, expr_str: &str) -> Result<i32, LangError> {
+        if divisor == 0 {
+            Err(LangError::new_arithmetic_error(
+                "Division by zero".to_string(),
+                expr_str.to_string(),
+            ))
+        } else {
+            Ok(divisor)
+        }
+    }
+
+    /// Returns the source of the expression, if it is not a name, a literal or a value
+    pub fn get_source(&self) -> Option<&Source> {
+        match self {
+            Expr::Add(.., s)
+            | Expr::Sub(.., s)
+            | Expr::Mul(.., s)
+            | Expr::Div(.., s)
+            | Expr::Mod(.., s)
+            | Expr::Equal(.., s)
+            | Expr::GreaterThan(.., s)
+            | Expr::GreaterEqualThan(.., s)
+            | Expr::LessThan(.., s)
+            | Expr::LessEqualThan(.., s)
+            | Expr::NEqual(.., s)
+            | Expr::And(.., s)
+            | Expr::Or(.., s)
+            | Expr::Not(.., s)
+            | Expr::If(.., s)
+            | Expr::Let(.., s)
+            | Expr::Set(.., s)
+            | Expr::Sequence(.., s)
+            | Expr::For(.., s)
+            | Expr::While(.., s)
+            | Expr::Define(.., s)
+            | Expr::Call(.., s)
+            | Expr::Print(.., s)
+            | Expr::Enum(.., s)
+            | Expr::EnumConstruct(.., s)
+            | Expr::Variants(.., s)
+            | Expr::Match(.., s)
+            | Expr::Env(.., s)
+            | Expr::Exit(.., s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// Returns the position of the expression in the code it was read from
+    /// Names and literals have no position of their own
+    pub fn get_span(&self) -> Option<Span> {
+        self.get_source().and_then(Source::get_span)
+    }
+
+    /// Returns the sub-expressions of the expression
+    pub(crate) fn children(&self) -> Vec<&Expr> {
+        match self {
+            Expr::Add(x, y, _)
+            | Expr::Sub(x, y, _)
+            | Expr::Mul(x, y, _)
+            | Expr::Div(x, y, _)
+            | Expr::Mod(x, y, _)
+            | Expr::Equal(x, y, _)
+            | Expr::GreaterThan(x, y, _)
+            | Expr::GreaterEqualThan(x, y, _)
+            | Expr::LessThan(x, y, _)
+            | Expr::LessEqualThan(x, y, _)
+            | Expr::NEqual(x, y, _)
+            | Expr::And(x, y, _)
+            | Expr::Or(x, y, _)
+            | Expr::Set(x, y, _)
+            | Expr::While(x, y, _) => vec![x.as_ref(), y.as_ref()],
+            Expr::Let(x, _, y, _) => vec![x.as_ref(), y.as_ref()],
+            Expr::Not(x, _) | Expr::Variants(x, _) | Expr::Exit(x, _) | Expr::Env(x, _) => {
+                vec![x.as_ref()]
+            }
+            Expr::If(b, x, y, _) => vec![b.as_ref(), x.as_ref(), y.as_ref()],
+            Expr::For(var, begin, end, core, _) => {
+                vec![var.as_ref(), begin.as_ref(), end.as_ref(), core.as_ref()]
+            }
+            Expr::Sequence(v, _) | Expr::Print(v, _) | Expr::List(v) => v.iter().collect(),
+            Expr::Define(name, _, _, core, _) => vec![name.as_ref(), core.as_ref()],
+            Expr::Call(name, args, _) => {
+                std::iter::once(name.as_ref()).chain(args.iter()).collect()
+            }
+            Expr::Enum(name, _, _) => vec![name.as_ref()],
+            Expr::EnumConstruct(_, args, _) => args.iter().collect(),
+            Expr::Match(x, arms, _) => std::iter::once(x.as_ref())
+                .chain(arms.iter().map(|(_, body)| body))
+                .collect(),
+            _ => vec![],
+        }
     }
 
     /// Execute the program represented by the token tree
     pub fn exec(&self) -> Result<Self, LangError> {
+        self.exec_with(&mut Context::stdio(vec![]))
+    }
+
+    /// Execute the program, with the arguments and the streams of the context
+    pub(crate) fn exec_with(&self, context: &mut Context) -> Result<Self, LangError> {
+        context.start();
         let mut variables: HashMap<String, Expr> = HashMap::new();
         let mut functions: HashMap<String, Function> = HashMap::new();
-        let mut enums: HashMap<String, u32> = HashMap::new();
-        self.evaluate(&mut variables, &mut functions, &mut enums)
+        let mut enums: HashMap<String, Vec<Variant>> = builtin_enums();
+        self.evaluate(&mut variables, &mut functions, &mut enums, context)
+    }
+
+    /// Returns `(option.some VALUE)`, or `option.none`
+    pub(crate) fn option(value: Option<Expr>) -> Self {
+        match value {
+            Some(value) => Expr::EnumValue {
+                enum_name: "option".to_string(),
+                variant: "some".to_string(),
+                fields: vec![value],
+            },
+            None => Expr::EnumValue {
+                enum_name: "option".to_string(),
+                variant: "none".to_string(),
+                fields: vec![],
+            },
+        }
+    }
+
+    pub(crate) fn evaluate(
+        &self,
+        variables: &mut HashMap<String, Expr>,
+        functions: &mut HashMap<String, Function>,
+        enums: &mut HashMap<String, Vec<Variant>>,
+        context: &mut Context,
+    ) -> Result<Self, LangError> {
+        context.step()?;
+        let hooks = context.take_hooks();
+        if hooks.is_empty() {
+            return self
+                .evaluate_node(variables, functions, enums, context)
+                .map_err(|err| err.with_span(self.get_span()));
+        }
+        self.evaluate_with_hooks(hooks, variables, functions, enums, context)
+    }
+
+    /// Evaluates the expression between the calls to the hooks taken from the context
+    fn evaluate_with_hooks(
+        &self,
+        mut hooks: Vec<Box<dyn Hook>>,
+        variables: &mut HashMap<String, Expr>,
+        functions: &mut HashMap<String, Function>,
+        enums: &mut HashMap<String, Vec<Variant>>,
+        context: &mut Context,
+    ) -> Result<Self, LangError> {
+        let before = hooks
+            .iter_mut()
+            .try_for_each(|h| h.before(self, variables, functions, enums, context));
+        context.restore_hooks(hooks);
+        before?;
+        let result = self
+            .evaluate_node(variables, functions, enums, context)
+            .map_err(|err| err.with_span(self.get_span()));
+        let mut hooks = context.take_hooks();
+        for h in hooks.iter_mut() {
+            h.after(self, &result, context);
+        }
+        context.restore_hooks(hooks);
+        result
     }
 
-    fn evaluate(
+    /// Evaluates the two operands, and returns the result of the operation on their values
+    /// Each operation has its own function, so that the evaluation of nested expressions only
+    /// takes the stack of the operations being evaluated
+    fn binary(
+        x: &Expr,
+        y: &Expr,
+        variables: &mut HashMap<String, Expr>,
+        functions: &mut HashMap<String, Function>,
+        enums: &mut HashMap<String, Vec<Variant>>,
+        context: &mut Context,
+        operation: impl Fn(Expr, Expr) -> Result<Expr, LangError>,
+    ) -> Result<Self, LangError> {
+        let u = x.evaluate(variables, functions, enums, context)?;
+        let v = y.evaluate(variables, functions, enums, context)?;
+        operation(u, v)
+    }
+
+    /// Evaluates the expression, without calling the hooks of the context
+    fn evaluate_node(
         &self,
-        mut variables: &mut HashMap<String, Expr>,
-        mut functions: &mut HashMap<String, Function>,
-        mut enums: &mut HashMap<String, u32>,
+        variables: &mut HashMap<String, Expr>,
+        functions: &mut HashMap<String, Function>,
+        enums: &mut HashMap<String, Vec<Variant>>,
+        context: &mut Context,
     ) -> Result<Self, LangError> {
         match self {
-            Expr::Add(x, y, s) => x.evaluate(variables, functions, enums)?.arith_operation(
-                y.evaluate(variables, functions, enums)?,
-                |u, v| Expr::Number(u + v),
-                |x| x.get_num(s.to_string()),
-            ),
-            Expr::Sub(x, y, s) => x.evaluate(variables, functions, enums)?.arith_operation(
-                y.evaluate(variables, functions, enums)?,
-                |u, v| Expr::Number(u - v),
-                |x| x.get_num(s.to_string()),
-            ),
-            Expr::Mul(x, y, s) => x.evaluate(variables, functions, enums)?.arith_operation(
-                y.evaluate(variables, functions, enums)?,
-                |u, v| Expr::Number(u * v),
-                |x| x.get_num(s.to_string()),
-            ),
-            Expr::Div(x, y, s) => x.evaluate(variables, functions, enums)?.arith_operation(
-                y.evaluate(variables, functions, enums)?,
-                |u, v| Expr::Number(u / v),
-                |x| x.get_num(s.to_string()),
-            ),
-            Expr::Mod(x, y, s) => x.evaluate(variables, functions, enums)?.arith_operation(
-                y.evaluate(variables, functions, enums)?,
-                |u, v| Expr::Number(u % v),
-                |x| x.get_num(s.to_string()),
-            ),
-            Expr::Equal(x, y, s) => x.evaluate(variables, functions, enums)?.arith_operation(
-                y.evaluate(variables, functions, enums)?,
-                |u, v| Expr::Bool(u == v),
-                |x| x.get_num(s.to_string()),
-            ),
-            Expr::NEqual(x, y, s) => x.evaluate(variables, functions, enums)?.arith_operation(
-                y.evaluate(variables, functions, enums)?,
-                |u, v| Expr::Bool(u != v),
-                |x| x.get_num(s.to_string()),
-            ),
-            Expr::GreaterThan(x, y, s) => x.evaluate(variables, functions, enums)?.arith_operation(
-                y.evaluate(variables, functions, enums)?,
-                |u, v| Expr::Bool(u > v),
-                |x| x.get_num(s.to_string()),
-            ),
-            Expr::LessThan(x, y, s) => x.evaluate(variables, functions, enums)?.arith_operation(
-                y.evaluate(variables, functions, enums)?,
-                |u, v| Expr::Bool(u < v),
-                |x| x.get_num(s.to_string()),
-            ),
+            Expr::Add(x, y, s) => {
+                Expr::binary(x, y, variables, functions, enums, context, |u, v| {
+                    u.arith_operation(
+                        v,
+                        |u: i32, v| Expr::checked(u.checked_add(v), s),
+                        |x| x.get_num(s.to_string()),
+                    )
+                })
+            }
+            Expr::Sub(x, y, s) => {
+                Expr::binary(x, y, variables, functions, enums, context, |u, v| {
+                    u.arith_operation(
+                        v,
+                        |u: i32, v| Expr::checked(u.checked_sub(v), s),
+                        |x| x.get_num(s.to_string()),
+                    )
+                })
+            }
+            Expr::Mul(x, y, s) => {
+                Expr::binary(x, y, variables, functions, enums, context, |u, v| {
+                    u.arith_operation(
+                        v,
+                        |u: i32, v| Expr::checked(u.checked_mul(v), s),
+                        |x| x.get_num(s.to_string()),
+                    )
+                })
+            }
+            Expr::Div(x, y, s) => {
+                Expr::binary(x, y, variables, functions, enums, context, |u, v| {
+                    u.arith_operation(
+                        v,
+                        |u: i32, v| Expr::checked(u.checked_div(Expr::non_zero(v, s)?), s),
+                        |x| x.get_num(s.to_string()),
+                    )
+                })
+            }
+            Expr::Mod(x, y, s) => {
+                Expr::binary(x, y, variables, functions, enums, context, |u, v| {
+                    u.arith_operation(
+                        v,
+                        |u: i32, v| Expr::checked(u.checked_rem(Expr::non_zero(v, s)?), s),
+                        |x| x.get_num(s.to_string()),
+                    )
+                })
+            }
+            Expr::GreaterThan(x, y, s) => {
+                Expr::binary(x, y, variables, functions, enums, context, |u, v| {
+                    u.arith_operation(
+                        v,
+                        |u, v| Ok(Expr::Bool(u > v)),
+                        |x| x.get_num(s.to_string()),
+                    )
+                })
+            }
+            Expr::LessThan(x, y, s) => {
+                Expr::binary(x, y, variables, functions, enums, context, |u, v| {
+                    u.arith_operation(
+                        v,
+                        |u, v| Ok(Expr::Bool(u < v)),
+                        |x| x.get_num(s.to_string()),
+                    )
+                })
+            }
             Expr::GreaterEqualThan(x, y, s) => {
-                x.evaluate(variables, functions, enums)?.arith_operation(
-                    y.evaluate(variables, functions, enums)?,
-                    |u, v| Expr::Bool(u >= v),
-                    |x| x.get_num(s.to_string()),
-                )
+                Expr::binary(x, y, variables, functions, enums, context, |u, v| {
+                    u.arith_operation(
+                        v,
+                        |u, v| Ok(Expr::Bool(u >= v)),
+                        |x| x.get_num(s.to_string()),
+                    )
+                })
             }
             Expr::LessEqualThan(x, y, s) => {
-                x.evaluate(variables, functions, enums)?.arith_operation(
-                    y.evaluate(variables, functions, enums)?,
-                    |u, v| Expr::Bool(u <= v),
-                    |x| x.get_num(s.to_string()),
-                )
-            }
-            Expr::And(x, y, s) => x.evaluate(variables, functions, enums)?.arith_operation(
-                y.evaluate(variables, functions, enums)?,
-                |u, v| Expr::Bool(u && v),
-                |x| x.get_bool(s.to_string()),
-            ),
-            Expr::Or(x, y, s) => x.evaluate(variables, functions, enums)?.arith_operation(
-                y.evaluate(variables, functions, enums)?,
-                |u, v| Expr::Bool(u || v),
-                |x| x.get_bool(s.to_string()),
-            ),
-
-            Expr::Not(x, _s) => Ok((!x.evaluate(variables, functions, enums)?)?),
+                Expr::binary(x, y, variables, functions, enums, context, |u, v| {
+                    u.arith_operation(
+                        v,
+                        |u, v| Ok(Expr::Bool(u <= v)),
+                        |x| x.get_num(s.to_string()),
+                    )
+                })
+            }
+            Expr::And(x, y, s) => {
+                Expr::binary(x, y, variables, functions, enums, context, |u, v| {
+                    u.arith_operation(
+                        v,
+                        |u, v| Ok(Expr::Bool(u && v)),
+                        |x| x.get_bool(s.to_string()),
+                    )
+                })
+            }
+            Expr::Or(x, y, s) => {
+                Expr::binary(x, y, variables, functions, enums, context, |u, v| {
+                    u.arith_operation(
+                        v,
+                        |u, v| Ok(Expr::Bool(u || v)),
+                        |x| x.get_bool(s.to_string()),
+                    )
+                })
+            }
+            Expr::Equal(x, y, s) => {
+                Expr::binary(x, y, variables, functions, enums, context, |u, v| {
+                    Ok(Expr::Bool(u.equals(&v, s.to_string())?))
+                })
+            }
+            Expr::NEqual(x, y, s) => {
+                Expr::binary(x, y, variables, functions, enums, context, |u, v| {
+                    Ok(Expr::Bool(!u.equals(&v, s.to_string())?))
+                })
+            }
+            Expr::Not(x, _s) => Ok((!x.evaluate(variables, functions, enums, context)?)?),
             Expr::Number(x) => Ok(Expr::Number(*x)),
             Expr::Bool(x) => Ok(Expr::Bool(*x)),
             Expr::Str(x) => Ok(Expr::Str(x.to_string())),
-            Expr::Var(x) => {
-                let p = variables.get(x);
-                if let Some(e) = p {
-                    e.clone()
-                        .evaluate(&mut variables, &mut functions, &mut enums)
-                } else {
-                    Ok(Expr::Var(x.to_string()))
-                }
-            }
-            Expr::Let(name, x, s) => {
-                let result = x.evaluate(variables, functions, enums)?;
-                let var_name = name.get_var(s.to_string())?;
-                variables.insert(var_name, result);
-                Ok(Expr::Empty)
+            Expr::Var(x) => match variables.get(x) {
+                Some(e) => Ok(e.clone()),
+                None => Err(LangError::new_undefined_variable_error(
+                    x.to_string(),
+                    x.to_string(),
+                )),
+            },
+            Expr::Symbol(x) => Ok(Expr::Symbol(x.to_string())),
+            Expr::Let(name, annotation, x, s) => {
+                Expr::evaluate_let(name, annotation, x, s, variables, functions, enums, context)
             }
             Expr::Set(name, x, s) => {
-                let result = x.evaluate(variables, functions, enums)?;
-                let var_name = name.get_var(s.to_string())?;
-
-                let opt_previous = variables.insert(var_name.clone(), result);
-                if opt_previous.is_none() {
-                    Err(LangError::new_undefined_variable_error(
-                        var_name,
-                        s.to_string(),
-                    ))
-                } else {
-                    Ok(Expr::Empty)
-                }
+                Expr::evaluate_set(name, x, s, variables, functions, enums, context)
             }
             Expr::Empty => Ok(Expr::Empty),
-            Expr::Sequence(v, s) => {
-                let mut result = None;
-                for e in v {
-                    result = Some(e.evaluate(variables, functions, enums)?);
-                }
-                if result.is_none() {
-                    Ok(Expr::Empty)
-                } else {
-                    Ok(result.unwrap())
-                }
+            Expr::Sequence(v, _s) => {
+                Expr::evaluate_sequence(v, variables, functions, enums, context)
             }
             Expr::If(b, x, y, s) => {
-                let bool_evaluated = b.evaluate(variables, functions, enums)?;
-                if let Expr::Bool(bb) = bool_evaluated {
-                    if bb {
-                        x.evaluate(variables, functions, enums)
-                    } else {
-                        y.evaluate(variables, functions, enums)
-                    }
-                } else {
-                    Err(LangError::new_type_error(
-                        Type::Bool,
-                        bool_evaluated.get_type(),
-                        s.to_string(),
-                    ))
-                }
+                Expr::evaluate_if(b, x, y, s, variables, functions, enums, context)
             }
-            Expr::For(var, begin, end, core, s) => {
-                let inf = begin.get_num(s.to_string())?;
-                let sup = end.get_num(s.to_string())?;
-                let var_name = var.get_var(s.to_string())?;
+            Expr::For(var, begin, end, core, s) => Expr::evaluate_for(
+                var, begin, end, core, s, variables, functions, enums, context,
+            ),
+            Expr::While(bool_exp, core, s) => {
+                Expr::evaluate_while(bool_exp, core, s, variables, functions, enums, context)
+            }
+            Expr::Define(name, params, ret, core, s) => {
+                Expr::evaluate_define(name, params, ret, core, s, functions, enums)
+            }
+            Expr::Call(name, args, s) => {
+                Expr::evaluate_call(name, args, s, variables, functions, enums, context)
+            }
+            Expr::Print(x, _) => Expr::evaluate_print(x, variables, functions, enums, context),
+            Expr::Input => Expr::evaluate_input(context),
+            Expr::Enum(enum_name, variants, s) => {
+                Expr::evaluate_enum(enum_name, variants, s, enums)
+            }
+            Expr::Match(x, arms, s) => {
+                Expr::evaluate_match(x, arms, s, variables, functions, enums, context)
+            }
+            Expr::List(v) => Expr::evaluate_list(v, variables, functions, enums, context),
+            Expr::EnumElement(x) => Expr::construct(x, vec![], x.to_string(), enums),
+            Expr::EnumConstruct(x, args, s) => {
+                Expr::evaluate_enum_construct(x, args, s, variables, functions, enums, context)
+            }
+            Expr::EnumValue { .. } => Ok(self.clone()),
+            Expr::Args => Expr::evaluate_args(context),
+            Expr::Env(name, s) => Expr::evaluate_env(name, s, variables, functions, enums, context),
+            Expr::Exit(code, s) => {
+                Expr::evaluate_exit(code, s, variables, functions, enums, context)
+            }
+            Expr::Variants(enum_name, s) => Expr::evaluate_variants(enum_name, s, enums, context),
+        }
+    }
 
-                let previous_value = variables.insert(var_name.clone(), Expr::Number(inf));
-                for i in inf..sup {
-                    variables.insert(var_name.clone(), Expr::Number(i));
-                    core.evaluate(variables, functions, enums)?;
-                }
+    #[allow(clippy::too_many_arguments)]
+    fn evaluate_let(
+        name: &Expr,
+        annotation: &Option<Type>,
+        x: &Expr,
+        s: &Source,
+        variables: &mut HashMap<String, Expr>,
+        functions: &mut HashMap<String, Function>,
+        enums: &mut HashMap<String, Vec<Variant>>,
+        context: &mut Context,
+    ) -> Result<Self, LangError> {
+        if let Some(t) = annotation {
+            t.check_known(enums, s)?;
+        }
+        let result = x.evaluate(variables, functions, enums, context)?;
+        result.check_type(annotation, s.to_string())?;
+        let var_name = name.get_var(s.to_string())?;
+        variables.insert(var_name, result);
+        Ok(Expr::Empty)
+    }
+
+    fn evaluate_set(
+        name: &Expr,
+        x: &Expr,
+        s: &Source,
+        variables: &mut HashMap<String, Expr>,
+        functions: &mut HashMap<String, Function>,
+        enums: &mut HashMap<String, Vec<Variant>>,
+        context: &mut Context,
+    ) -> Result<Self, LangError> {
+        let result = x.evaluate(variables, functions, enums, context)?;
+        let var_name = name.get_var(s.to_string())?;
+
+        let opt_previous = variables.insert(var_name.clone(), result);
+        if opt_previous.is_none() {
+            Err(LangError::new_undefined_variable_error(
+                var_name,
+                s.to_string(),
+            ))
+        } else {
+            Ok(Expr::Empty)
+        }
+    }
 
-                let _ = match previous_value {
-                    Some(p) => variables.insert(var_name, p),
-                    None => variables.remove(&var_name),
-                };
-                Ok(Expr::Empty)
+    fn evaluate_sequence(
+        v: &[Expr],
+        variables: &mut HashMap<String, Expr>,
+        functions: &mut HashMap<String, Function>,
+        enums: &mut HashMap<String, Vec<Variant>>,
+        context: &mut Context,
+    ) -> Result<Self, LangError> {
+        let mut result = Expr::Empty;
+        for e in v {
+            result = e.evaluate(variables, functions, enums, context)?;
+        }
+        Ok(result)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn evaluate_if(
+        b: &Expr,
+        x: &Expr,
+        y: &Expr,
+        s: &Source,
+        variables: &mut HashMap<String, Expr>,
+        functions: &mut HashMap<String, Function>,
+        enums: &mut HashMap<String, Vec<Variant>>,
+        context: &mut Context,
+    ) -> Result<Self, LangError> {
+        let bool_evaluated = b.evaluate(variables, functions, enums, context)?;
+        if let Expr::Bool(bb) = bool_evaluated {
+            if bb {
+                x.evaluate(variables, functions, enums, context)
+            } else {
+                y.evaluate(variables, functions, enums, context)
             }
-            Expr::While(bool_exp, core, s) => {
-                let mut bool_val = bool_exp
-                    .evaluate(variables, functions, enums)?
-                    .get_bool(s.to_string())?;
-                while bool_val {
-                    core.evaluate(&mut variables, &mut functions, &mut enums)?;
-                    bool_val = bool_exp
-                        .evaluate(variables, functions, enums)?
-                        .get_bool(s.to_string())?;
-                }
-                Ok(Expr::Empty)
-            }
-
-            Expr::Define(name, args, core, s) => {
-                let func_name = name.get_var(s.to_string())?;
-                let new_function = Function::new(
-                    func_name.clone(),
-                    args.iter()
-                        .map(|a| {
-                            a.evaluate(variables, functions, enums)
-                                .unwrap()
-                                .get_var(s.to_string())
-                                .unwrap()
+        } else {
+            Err(LangError::new_type_error(
+                Type::Bool,
+                bool_evaluated.get_type(),
+                s.to_string(),
+            ))
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn evaluate_for(
+        var: &Expr,
+        begin: &Expr,
+        end: &Expr,
+        core: &Expr,
+        s: &Source,
+        variables: &mut HashMap<String, Expr>,
+        functions: &mut HashMap<String, Function>,
+        enums: &mut HashMap<String, Vec<Variant>>,
+        context: &mut Context,
+    ) -> Result<Self, LangError> {
+        let inf = begin
+            .evaluate(variables, functions, enums, context)?
+            .get_num(s.to_string())?;
+        let sup = end
+            .evaluate(variables, functions, enums, context)?
+            .get_num(s.to_string())?;
+        let var_name = var.get_var(s.to_string())?;
+
+        let previous_value = variables.insert(var_name.clone(), Expr::Number(inf));
+        for i in inf..sup {
+            variables.insert(var_name.clone(), Expr::Number(i));
+            core.evaluate(variables, functions, enums, context)?;
+        }
+
+        let _ = match previous_value {
+            Some(p) => variables.insert(var_name, p),
+            None => variables.remove(&var_name),
+        };
+        Ok(Expr::Empty)
+    }
+
+    fn evaluate_while(
+        bool_exp: &Expr,
+        core: &Expr,
+        s: &Source,
+        variables: &mut HashMap<String, Expr>,
+        functions: &mut HashMap<String, Function>,
+        enums: &mut HashMap<String, Vec<Variant>>,
+        context: &mut Context,
+    ) -> Result<Self, LangError> {
+        let mut bool_val = bool_exp
+            .evaluate(variables, functions, enums, context)?
+            .get_bool(s.to_string())?;
+        while bool_val {
+            core.evaluate(variables, functions, enums, context)?;
+            bool_val = bool_exp
+                .evaluate(variables, functions, enums, context)?
+                .get_bool(s.to_string())?;
+        }
+        Ok(Expr::Empty)
+    }
+
+    fn evaluate_define(
+        name: &Expr,
+        params: &[Param],
+        ret: &Option<Type>,
+        core: &Rc<Expr>,
+        s: &Source,
+        functions: &mut HashMap<String, Function>,
+        enums: &mut HashMap<String, Vec<Variant>>,
+    ) -> Result<Self, LangError> {
+        let func_name = name.get_var(s.to_string())?;
+        let annotations = params.iter().filter_map(|p| p.get_annotation());
+        for t in annotations.chain(ret.clone()) {
+            t.check_known(enums, s)?;
+        }
+        let new_function = Function::new(
+            func_name.clone(),
+            params.to_vec(),
+            ret.clone(),
+            core.clone(),
+        );
+        functions.insert(func_name, new_function);
+        Ok(Expr::Empty)
+    }
+
+    fn evaluate_call(
+        name: &Expr,
+        args: &[Expr],
+        s: &Source,
+        variables: &mut HashMap<String, Expr>,
+        functions: &mut HashMap<String, Function>,
+        enums: &mut HashMap<String, Vec<Variant>>,
+        context: &mut Context,
+    ) -> Result<Self, LangError> {
+        let func_name = name.get_var(s.to_string())?;
+
+        let evaluated_args = args
+            .iter()
+            .map(|a| a.evaluate(variables, functions, enums, context))
+            .collect::<Result<Vec<Expr>, LangError>>()?;
+
+        Expr::call(
+            &func_name,
+            evaluated_args,
+            variables,
+            functions,
+            enums,
+            context,
+            s.to_string(),
+        )
+    }
+
+    fn evaluate_print(
+        x: &[Expr],
+        variables: &mut HashMap<String, Expr>,
+        functions: &mut HashMap<String, Function>,
+        enums: &mut HashMap<String, Vec<Variant>>,
+        context: &mut Context,
+    ) -> Result<Self, LangError> {
+        context.require(Capability::Stdout, "print")?;
+        for e in x.iter() {
+            let value = e.evaluate(variables, functions, enums, context)?;
+            write!(context.get_output(), "{}", value)
+                .map_err(|e| LangError::new_io_error(e.to_string(), "print".to_string()))?;
+        }
+        context
+            .get_output()
+            .flush()
+            .map_err(|e| LangError::new_io_error(e.to_string(), "print".to_string()))?;
+        Ok(Expr::Empty)
+    }
+
+    fn evaluate_input(context: &mut Context) -> Result<Self, LangError> {
+        context.require(Capability::Stdin, "input")?;
+        let mut b = String::new();
+        context
+            .get_input()
+            .read_line(&mut b)
+            .map_err(|e| LangError::new_io_error(e.to_string(), "input".to_string()))?;
+        match b.trim().parse::<i32>() {
+            Ok(x) => Ok(Expr::Number(x)),
+            Err(_) => context.allocate(Expr::Str(b), "input"),
+        }
+    }
+
+    fn evaluate_enum(
+        enum_name: &Expr,
+        variants: &[Variant],
+        s: &Source,
+        enums: &mut HashMap<String, Vec<Variant>>,
+    ) -> Result<Self, LangError> {
+        let str_enum_name = enum_name.get_var(s.to_string())?;
+        enums.insert(str_enum_name, variants.to_vec());
+        Ok(Expr::Empty)
+    }
+
+    fn evaluate_match(
+        x: &Expr,
+        arms: &[(Pattern, Expr)],
+        s: &Source,
+        variables: &mut HashMap<String, Expr>,
+        functions: &mut HashMap<String, Function>,
+        enums: &mut HashMap<String, Vec<Variant>>,
+        context: &mut Context,
+    ) -> Result<Self, LangError> {
+        let value = x.evaluate(variables, functions, enums, context)?;
+        for (pattern, body) in arms.iter() {
+            let mut bindings = Vec::new();
+            if !pattern.matches(&value, &mut bindings, variables, functions, enums, context)? {
+                continue;
+            }
+            // Bind the captured names for the duration of the arm
+            let previous_values: Vec<(String, Option<Expr>)> = bindings
+                .into_iter()
+                .map(|(name, v)| (name.clone(), variables.insert(name, v)))
+                .collect();
+
+            let result = body.evaluate(variables, functions, enums, context);
+
+            // Restore the values
+            restore(variables, previous_values);
+            return result;
+        }
+        Err(LangError::new_match_error(value.to_string(), s.to_string()))
+    }
+
+    fn evaluate_list(
+        v: &[Expr],
+        variables: &mut HashMap<String, Expr>,
+        functions: &mut HashMap<String, Function>,
+        enums: &mut HashMap<String, Vec<Variant>>,
+        context: &mut Context,
+    ) -> Result<Self, LangError> {
+        let list = Expr::List(
+            v.iter()
+                .map(|e| e.evaluate(variables, functions, enums, context))
+                .collect::<Result<Vec<Expr>, LangError>>()?,
+        );
+        context.allocate(list, "list")
+    }
+
+    fn evaluate_enum_construct(
+        x: &str,
+        args: &[Expr],
+        s: &Source,
+        variables: &mut HashMap<String, Expr>,
+        functions: &mut HashMap<String, Function>,
+        enums: &mut HashMap<String, Vec<Variant>>,
+        context: &mut Context,
+    ) -> Result<Self, LangError> {
+        let fields = args
+            .iter()
+            .map(|e| e.evaluate(variables, functions, enums, context))
+            .collect::<Result<Vec<Expr>, LangError>>()?;
+        context.allocate(Expr::construct(x, fields, s.to_string(), enums)?, s)
+    }
+
+    fn evaluate_args(context: &mut Context) -> Result<Self, LangError> {
+        let args = Expr::List(
+            context
+                .get_args()
+                .iter()
+                .map(|a| Expr::Str(a.to_string()))
+                .collect(),
+        );
+        context.allocate(args, "args")
+    }
+
+    fn evaluate_env(
+        name: &Expr,
+        s: &Source,
+        variables: &mut HashMap<String, Expr>,
+        functions: &mut HashMap<String, Function>,
+        enums: &mut HashMap<String, Vec<Variant>>,
+        context: &mut Context,
+    ) -> Result<Self, LangError> {
+        context.require(Capability::Env, s)?;
+        let name = name
+            .evaluate(variables, functions, enums, context)?
+            .get_str(s.to_string())?;
+        context.allocate(Expr::option(std::env::var(name).ok().map(Expr::Str)), s)
+    }
+
+    fn evaluate_exit(
+        code: &Expr,
+        s: &Source,
+        variables: &mut HashMap<String, Expr>,
+        functions: &mut HashMap<String, Function>,
+        enums: &mut HashMap<String, Vec<Variant>>,
+        context: &mut Context,
+    ) -> Result<Self, LangError> {
+        let code = code
+            .evaluate(variables, functions, enums, context)?
+            .get_num(s.to_string())?;
+        // The operating system keeps only the lowest byte of the status
+        match code {
+            0..=255 => Err(LangError::new_exit(code)),
+            _ => Err(LangError::new_arithmetic_error(
+                format!(
+                    "Exit status {} out of range (statuses go from 0 to 255)",
+                    code
+                ),
+                s.to_string(),
+            )),
+        }
+    }
+
+    fn evaluate_variants(
+        enum_name: &Expr,
+        s: &Source,
+        enums: &mut HashMap<String, Vec<Variant>>,
+        context: &mut Context,
+    ) -> Result<Self, LangError> {
+        let str_enum_name = enum_name.get_var(s.to_string())?;
+        match enums.get(&str_enum_name) {
+            Some(variants) => context.allocate(
+                Expr::List(
+                    variants
+                        .iter()
+                        .filter(|v| v.get_fields().is_empty())
+                        .map(|v| Expr::EnumValue {
+                            enum_name: str_enum_name.to_string(),
+                            variant: v.get_name(),
+                            fields: vec![],
                         })
                         .collect(),
-                    core.clone(),
-                );
-                functions.insert(func_name, new_function);
-                Ok(Expr::Empty)
+                ),
+                s,
+            ),
+            None => Err(LangError::new_undefined_variable_error(
+                str_enum_name,
+                s.to_string(),
+            )),
+        }
+    }
+
+    /// Calls the function with the evaluated arguments
+    /// If it does not exist, or is given a wrong number of arguments, returns an error
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn call(
+        func_name: &str,
+        evaluated_args: Vec<Expr>,
+        variables: &mut HashMap<String, Expr>,
+        functions: &mut HashMap<String, Function>,
+        enums: &mut HashMap<String, Vec<Variant>>,
+        context: &mut Context,
+        expr_str: String,
+    ) -> Result<Self, LangError> {
+        let function = match (functions.get(func_name), context.get_native(func_name)) {
+            (Some(function), _) => function.clone(),
+            // Functions defined with `def` shadow the native ones
+            (None, Some(native)) => {
+                let result = native.call(evaluated_args, expr_str.to_string())?;
+                return context.allocate(result, &expr_str);
             }
-            Expr::Call(name, args, s) => {
-                let func_name = name.get_var(s.to_string())?;
+            (None, None) => {
+                return Err(LangError::new_undefined_variable_error(
+                    func_name.to_string(),
+                    expr_str.to_string(),
+                ))
+            }
+        };
+        let (min, max) = function.arity();
+        if evaluated_args.len() < min || max.is_some_and(|max| evaluated_args.len() > max) {
+            return Err(LangError::new_arity_error(
+                function.get_name(),
+                min,
+                max,
+                evaluated_args.len(),
+                expr_str.to_string(),
+            ));
+        }
 
-                let evaluated_args: Vec<Expr> = args
-                    .iter()
-                    .map(|a| {
-                        a.evaluate(&mut variables, &mut functions, &mut enums)
-                            .unwrap()
-                    })
-                    .collect();
-
-                // println!("Calling function: {}, with args: {:?}", func_name, args);
-                let function = functions.get(&func_name);
-                if function.is_none() {
-                    return Err(LangError::new_undefined_variable_error(
-                        func_name.to_string(),
-                        s.to_string(),
-                    ));
-                }
-                let mut function = function.unwrap();
-                let func_args = function.get_args();
+        // Apply the function, storing the previous values of its parameters
+        let mut previous_values = Vec::new();
+        context.push_call(func_name, &expr_str)?;
+        let result = Expr::apply(
+            &function,
+            evaluated_args,
+            &mut previous_values,
+            variables,
+            functions,
+            enums,
+            context,
+            expr_str,
+        );
 
-                // Store the previous values of function.args
+        // Restore the values
+        context.pop_call();
+        restore(variables, previous_values);
+        // Return the result of the function call
+        result
+    }
 
-                let previous_values: Vec<Option<Expr>> = func_args
-                    .iter()
-                    .zip(evaluated_args.iter())
-                    .map(|(arg_name, arg_passed_value)| {
-                        variables.insert(arg_name.to_string(), arg_passed_value.clone())
-                    })
-                    .collect();
-
-                // Apply the function
-                let result =
-                    function
-                        .get_core()
-                        .evaluate(&mut variables, &mut functions, &mut enums)?;
-
-                // Restore the values
-                previous_values
-                    .iter()
-                    .zip(func_args.iter())
-                    .for_each(|(v, name)| {
-                        if v.is_some() {
-                            variables.insert(name.to_string(), v.clone().unwrap());
-                        } else {
-                            variables.remove(name);
-                        }
-                    });
-                // Return the result of the function call
-                Ok(result)
-            }
-            Expr::Print(x) => {
-                for e in x.iter() {
-                    print!("{}", e.evaluate(variables, functions, enums)?);
-                }
-                std::io::stdout().flush().unwrap();
-                Ok(Expr::Empty)
-            }
-            Expr::Input => {
-                let mut b = String::new();
-                let _ = std::io::stdin().read_line(&mut b).unwrap();
-                let x = b.trim().parse::<i32>();
-                if x.is_ok() {
-                    Ok(Expr::Number(x.unwrap()))
-                } else {
-                    Ok(Expr::Str(b.to_string()))
-                }
-            }
-            Expr::Enum(enum_name, names, s) => {
-                let str_enum_name = enum_name.get_var(s.to_string())?;
-                for e in names.iter() {
-                    let x = format!("{}.{}", str_enum_name, e.get_var(s.to_string())?);
-                    let size = enums.len();
-                    enums.insert(x, size as u32);
+    /// Binds the parameters of the function to the arguments and evaluates its core
+    /// The previous values of the parameters are pushed into `previous_values`
+    #[allow(clippy::too_many_arguments)]
+    fn apply(
+        function: &Function,
+        evaluated_args: Vec<Expr>,
+        previous_values: &mut Vec<(String, Option<Expr>)>,
+        variables: &mut HashMap<String, Expr>,
+        functions: &mut HashMap<String, Function>,
+        enums: &mut HashMap<String, Vec<Variant>>,
+        context: &mut Context,
+        expr_str: String,
+    ) -> Result<Self, LangError> {
+        let mut args = evaluated_args.into_iter();
+        for param in function.get_params() {
+            let value = if param.is_rest() {
+                context.allocate(Expr::List(args.by_ref().collect()), &expr_str)?
+            } else {
+                match (args.next(), param.get_default()) {
+                    (Some(value), _) => value,
+                    // Default values can use the previous parameters
+                    (None, Some(default)) => {
+                        default.evaluate(variables, functions, enums, context)?
+                    }
+                    (None, None) => Expr::Empty,
                 }
-                Ok(Expr::Empty)
-            }
-            Expr::EnumElement(x) => match enums.get(x) {
-                Some(e) => Ok(Expr::Number(e.clone() as i32)),
-                None => Err(LangError::new_undefined_variable_error(
-                    x.to_string(),
-                    "".to_string(),
-                )),
-            },
+            };
+            value.check_type(&param.get_annotation(), expr_str.clone())?;
+            previous_values.push((param.get_name(), variables.insert(param.get_name(), value)));
+        }
+        let result = function
+            .get_core()
+            .evaluate(variables, functions, enums, context)?;
+        result.check_type(&function.get_ret(), expr_str)?;
+        Ok(result)
+    }
+
+    /// Builds the item `ENUM_NAME.ITEM` of a declared enum with the given fields
+    /// If the number of fields is not the one of the declaration, returns an ArityError
+    fn construct(
+        name: &str,
+        fields: Vec<Expr>,
+        expr_str: String,
+        enums: &HashMap<String, Vec<Variant>>,
+    ) -> Result<Self, LangError> {
+        let (enum_name, variant_name) = name.split_once('.').unwrap_or((name, ""));
+        let variant = enums
+            .get(enum_name)
+            .and_then(|variants| variants.iter().find(|v| v.get_name() == variant_name));
+        match variant {
+            Some(v) if v.get_fields().len() == fields.len() => Ok(Expr::EnumValue {
+                enum_name: enum_name.to_string(),
+                variant: variant_name.to_string(),
+                fields,
+            }),
+            Some(v) => Err(LangError::new_arity_error(
+                name.to_string(),
+                v.get_fields().len(),
+                Some(v.get_fields().len()),
+                fields.len(),
+                expr_str,
+            )),
+            None => Err(LangError::new_undefined_variable_error(
+                name.to_string(),
+                expr_str,
+            )),
         }
     }
 
     /// Returns the token tree associated with the string
-    pub fn token_tree(s: &str) -> Self {
-        // dblet p = s.replace("\n", " ");
-        let p = s.replace("\n", " ");
-        let trimed_command_exp = p.trim();
-        if trimed_command_exp.chars().next().is_none() {
-            return Expr::Empty;
-        }
-        if trimed_command_exp.chars().next() != Some('(') {
-            // If it is a number
-            if p.contains(".") {
-                return Expr::EnumElement(s.to_string());
-            }
-            let x = p.trim().parse::<i32>();
-            if x.is_ok() {
-                Expr::Number(x.unwrap())
-            } else if p.trim().chars().take(1).next().unwrap() == '"' {
-                Expr::Str(p[1..p.len() - 1].to_string())
-            } else {
-                Expr::Var(s.to_string())
+    /// If the string is not a valid program, returns a SyntaxError
+    pub fn token_tree(s: &str) -> Result<Self, LangError> {
+        let expressions = read_spanned(s)?;
+        match expressions.as_slice() {
+            [] => Ok(Expr::Empty),
+            [single] => Expr::from_sexp(single),
+            // Several expressions are executed one after the other
+            [first, .., last] => Ok(Expr::Sequence(
+                expressions
+                    .iter()
+                    .map(Expr::from_sexp)
+                    .collect::<Result<Vec<Expr>, LangError>>()?,
+                Source::new(
+                    expressions
+                        .iter()
+                        .map(|e| e.to_string())
+                        .collect::<Vec<String>>()
+                        .join(" "),
+                    Span {
+                        start: first.start,
+                        end: last.end,
+                    },
+                    expressions.iter().map(Spanned::get_span).collect(),
+                ),
+            )),
+        }
+    }
+
+    /// Returns the token tree associated with a name, a number, a string, ...
+    pub(crate) fn from_atom(x: &str) -> Result<Self, LangError> {
+        if let Some(symbol) = x.strip_prefix('\'') {
+            // If it is a quoted symbol
+            if symbol.is_empty() {
+                return Err(LangError::new_syntax_error(
+                    "Expected a name after the quote".to_string(),
+                    x.to_string(),
+                ));
             }
+            Ok(Expr::Symbol(symbol.to_string()))
+        } else if is_enum_element(x) {
+            // If it is an enum item
+            Ok(Expr::EnumElement(x.to_string()))
+        } else if let Ok(n) = x.parse::<i32>() {
+            Ok(Expr::Number(n))
+        } else if x.len() >= 2 && x.starts_with('"') && x.ends_with('"') {
+            Ok(Expr::Str(x[1..x.len() - 1].to_string()))
+        } else if x == "true" || x == "false" {
+            Ok(Expr::Bool(x == "true"))
+        } else if x
+            .trim_start_matches('-')
+            .starts_with(|c: char| c.is_ascii_digit())
+        {
+            Err(LangError::new_syntax_error(
+                format!(
+                    "Invalid number (integers go from {} to {})",
+                    i32::MIN,
+                    i32::MAX
+                ),
+                x.to_string(),
+            ))
         } else {
-            // If it is an expression
-            let str_expressions: Vec<String> =
-                get_expressions(&trimed_command_exp[1..trimed_command_exp.len() - 1]);
-            // println!("EXPRESSIONS: {:?}", str_expressions);
-            let command = str_expressions[0].as_str();
-            // if str_expressions[0].chars().next() == Some('(') {
-            //     panic!("Syntax not accepted ...");
-            // } else {
-            match command {
-                    "+" => Expr::Add(
-                        Box::new(Expr::token_tree(str_expressions[1].as_str().trim())),
-                        Box::new(Expr::token_tree(str_expressions[2].as_str().trim())),
-                        trimed_command_exp.to_string(),
-                    ),
-                    "-" => Expr::Sub(
-                        Box::new(Expr::token_tree(str_expressions[1].as_str().trim())),
-                        Box::new(Expr::token_tree(str_expressions[2].as_str().trim())),
-                        trimed_command_exp.to_string(),
-                    ),
-                    "*" => Expr::Mul(
-                        Box::new(Expr::token_tree(str_expressions[1].as_str().trim())),
-                        Box::new(Expr::token_tree(str_expressions[2].as_str().trim())),
-                        trimed_command_exp.to_string(),
-                    ),
-                    "/" => Expr::Div(
-                        Box::new(Expr::token_tree(str_expressions[1].as_str().trim())),
-                        Box::new(Expr::token_tree(str_expressions[2].as_str().trim())),
-                        trimed_command_exp.to_string(),
-                    ),
-                    "%" => Expr::Mod(
-                        Box::new(Expr::token_tree(str_expressions[1].as_str().trim())),
-                        Box::new(Expr::token_tree(str_expressions[2].as_str().trim())),
-                        trimed_command_exp.to_string(),
-                    ),
-                    "=" => Expr::Equal(
-                        Box::new(Expr::token_tree(str_expressions[1].as_str().trim())),
-                        Box::new(Expr::token_tree(str_expressions[2].as_str().trim())),
-                        trimed_command_exp.to_string(),
-                    ),
-                    "!=" => Expr::NEqual(
-                        Box::new(Expr::token_tree(str_expressions[1].as_str().trim())),
-                        Box::new(Expr::token_tree(str_expressions[2].as_str().trim())),
-                        trimed_command_exp.to_string(),
-                    ),
-                    ">" => Expr::GreaterThan(
-                        Box::new(Expr::token_tree(str_expressions[1].as_str().trim())),
-                        Box::new(Expr::token_tree(str_expressions[2].as_str().trim())),
-                        trimed_command_exp.to_string(),
-                    ),
-                    ">=" => Expr::GreaterEqualThan(
-                        Box::new(Expr::token_tree(str_expressions[1].as_str().trim())),
-                        Box::new(Expr::token_tree(str_expressions[2].as_str().trim())),
-                        trimed_command_exp.to_string(),
-                    ),
-                    "<" => Expr::LessThan(
-                        Box::new(Expr::token_tree(str_expressions[1].as_str().trim())),
-                        Box::new(Expr::token_tree(str_expressions[2].as_str().trim())),
-                        trimed_command_exp.to_string(),
-                    ),
-                    "<=" => Expr::LessEqualThan(
-                        Box::new(Expr::token_tree(str_expressions[1].as_str().trim())),
-                        Box::new(Expr::token_tree(str_expressions[2].as_str().trim())),
-                        trimed_command_exp.to_string(),
-                    ),
-                    "&&" => Expr::And(
-                        Box::new(Expr::token_tree(str_expressions[1].as_str().trim())),
-                        Box::new(Expr::token_tree(str_expressions[2].as_str().trim())),
-                        trimed_command_exp.to_string(),
-                    ),
-                    "||" => Expr::Or(
-                        Box::new(Expr::token_tree(str_expressions[1].as_str().trim())),
-                        Box::new(Expr::token_tree(str_expressions[2].as_str().trim())),
-                        trimed_command_exp.to_string(),
-                    ),
-
-                    "!" => Expr::Not(
-                        Box::new(Expr::token_tree(str_expressions[1].as_str().trim())),
-                        trimed_command_exp.to_string(),
-                    ),
-                    "if" => Expr::If(
-                        Box::new(Expr::token_tree(str_expressions[1].as_str().trim())),
-                        Box::new(Expr::token_tree(str_expressions[2].as_str().trim())),
-                        Box::new(Expr::token_tree(str_expressions[3].as_str().trim())),
-                        trimed_command_exp.to_string(),
-                    ),
-                    "let" => Expr::Let(
-                        Box::new(Expr::token_tree(str_expressions[1].as_str().trim())),
-                        Box::new(Expr::token_tree(str_expressions[2].as_str().trim())),
-                        trimed_command_exp.to_string(),
-                    ),
-                    "set" => Expr::Set(
-                        Box::new(Expr::token_tree(str_expressions[1].as_str().trim())),
-                        Box::new(Expr::token_tree(str_expressions[2].as_str().trim())),
-                        s.to_string(),
-                    ),
-                    "def" => Expr::Define(
-                        Box::new(Expr::token_tree(str_expressions[1].as_str().trim())),
-                        str_expressions[2..str_expressions.len() - 1].iter().map(|e| Box::new(Expr::token_tree(e.as_str().trim()))).collect::<Vec<Box<Expr>>>(),
-                        Box::new(Expr::token_tree(str_expressions[str_expressions.len() - 1].as_str().trim())),
-                        s.to_string(),
-                    ),
-                    "call" => Expr::Call(
-                        Box::new(Expr::token_tree(str_expressions[1].as_str().trim())),
-                        str_expressions.iter().skip(2).map(|e| Box::new(Expr::token_tree(e.as_str().trim()))).collect::<Vec<Box<Expr>>>(),
-                        s.to_string(),
-                    ),
-                    "enum" => Expr::Enum(
-                        Box::new(Expr::token_tree(str_expressions[1].as_str().trim())),
-                        str_expressions.iter().skip(2).map(|e| Box::new(Expr::token_tree(e.as_str().trim()))).collect::<Vec<Box<Expr>>>(),
-                        s.to_string(),
-                    ),
-
-                    "print" => Expr::Print(
-                        str_expressions.iter().skip(1).map(|e| Box::new(Expr::token_tree(e.as_str().trim()))).collect::<Vec<Box<Expr>>>(),
-                    ),
-                    "input" => Expr::Input,
-                    "while" => Expr::While(
-                        Box::new(Expr::token_tree(str_expressions[1].as_str().trim())),
-                        Box::new(Expr::token_tree(str_expressions[2].as_str().trim())),
-                        trimed_command_exp.to_string(),
-                    ),
-                    "for" => Expr::For(
-                        Box::new(Expr::token_tree(str_expressions[1].as_str().trim())),
-                        Box::new(Expr::token_tree(str_expressions[2].as_str().trim())),
-                        Box::new(Expr::token_tree(str_expressions[3].as_str().trim())),
-                        Box::new(Expr::token_tree(str_expressions[4].as_str().trim())),
-                        trimed_command_exp.to_string(),
-                    ),
-                    _ => Expr::Sequence(str_expressions.iter().map(|e| Box::new(Expr::token_tree(e.as_str().trim()))).collect::<Vec<Box<Expr>>>(), trimed_command_exp.to_string())
-                    // _ => Expr::Empty,
-                }
-            // }
+            Ok(Expr::Var(x.to_string()))
         }
     }
+
+    /// Returns the token tree associated with the S-expression
+    /// A SyntaxError is positioned at the innermost S-expression raising it
+    pub(crate) fn from_sexp(e: &Spanned) -> Result<Self, LangError> {
+        Expr::from_spanned(e).map_err(|err| err.with_span(Some(e.get_span())))
+    }
+
+    fn from_spanned(e: &Spanned) -> Result<Self, LangError> {
+        if let Some(x) = e.atom() {
+            return Expr::from_atom(x);
+        }
+        let form = Form::new(e)?;
+        // Each form is built by its own function, to keep the frame of this recursive one small
+        match form.command {
+            "+" => form.binary(Expr::Add),
+            "-" => form.binary(Expr::Sub),
+            "*" => form.binary(Expr::Mul),
+            "/" => form.binary(Expr::Div),
+            "%" => form.binary(Expr::Mod),
+            "=" => form.binary(Expr::Equal),
+            "!=" => form.binary(Expr::NEqual),
+            ">" => form.binary(Expr::GreaterThan),
+            ">=" => form.binary(Expr::GreaterEqualThan),
+            "<" => form.binary(Expr::LessThan),
+            "<=" => form.binary(Expr::LessEqualThan),
+            "&&" => form.binary(Expr::And),
+            "||" => form.binary(Expr::Or),
+            "set" => form.binary(Expr::Set),
+            "while" => form.binary(Expr::While),
+            "!" => form.unary(Expr::Not),
+            "env" => form.unary(Expr::Env),
+            "exit" => form.unary(Expr::Exit),
+            "variants" => form.unary(Expr::Variants),
+            "if" => form.if_form(),
+            "let" => form.let_form(),
+            "def" => form.define(),
+            "call" => form.call(),
+            "enum" => form.enum_form(),
+            "print" => form.print(),
+            "input" => Ok(Expr::Input),
+            "args" => Ok(Expr::Args),
+            "for" => form.for_form(),
+            "match" => form.match_form(),
+            "list" => form.list(),
+            _ if is_enum_element(form.command) => form.enum_construct(),
+            _ => form.sequence(),
+        }
+    }
+}
+
+/// List read from the source, being turned into an `Expr` according to its first element
+struct Form<'a> {
+    e: &'a Spanned,
+    items: &'a [Spanned],
+    command: &'a str,
+    s: String,
 }
 
-fn get_expressions(s: &str) -> Vec<String> {
-    let mut v: Vec<String> = Vec::new();
-
-    let mut current_expr = String::new();
-    let mut p = s.replace("\n", " ");
-    p = p.replace("\t", " ");
-    p = p.replace("  ", " ");
-    // println!("P: {}", p);
-    let mut chars = p.chars();
-    let mut in_expr = false;
-    let mut par_count = 0;
-    let mut just_pushed = false;
-    let mut in_str = false;
-
-    while let Some(c) = chars.next() {
-        if c == ' ' && !in_expr && !just_pushed && !in_str {
-            v.push(current_expr.trim().to_string());
-            current_expr = String::new();
-            just_pushed = false;
-        } else if c == '(' {
-            par_count += 1;
-            current_expr.push(c);
-            in_expr = par_count >= 1;
-        } else if c == ')' && in_expr {
-            par_count -= 1;
-            in_expr = par_count != 0;
-            current_expr.push(c);
-            if !in_expr {
-                v.push(current_expr.trim().to_string());
-                current_expr = String::new();
-                just_pushed = true;
-            }
-        } else if c == '"' {
-            in_str = !in_str;
-            current_expr.push(c);
+impl<'a> Form<'a> {
+    /// Returns the form of the list, or a SyntaxError if it has too many elements
+    fn new(e: &'a Spanned) -> Result<Self, LangError> {
+        let items = e.get_items();
+        let s = e.to_string();
+        let command = items.first().and_then(Spanned::atom).unwrap_or("");
+        // Number of arguments of the forms that take a fixed number of them
+        let expected = match command {
+            "input" | "args" => Some(0),
+            "!" | "env" | "exit" | "variants" => Some(1),
+            "+" | "-" | "*" | "/" | "%" | "=" | "!=" | ">" | ">=" | "<" | "<=" | "&&" | "||"
+            | "set" | "while" => Some(2),
+            "let" if items.get(2).is_some_and(|x| x.is_atom(":")) => Some(4),
+            "let" => Some(2),
+            "if" => Some(3),
+            "for" => Some(4),
+            _ => None,
+        };
+        if let Some(n) = expected.filter(|n| items.len() > n + 1) {
+            return Err(LangError::new_syntax_error(
+                format!(
+                    "\"{}\" expects {} argument(s), but {} were given",
+                    command,
+                    n,
+                    items.len() - 1
+                ),
+                s,
+            ));
+        }
+        Ok(Form {
+            e,
+            items,
+            command,
+            s,
+        })
+    }
+
+    /// Returns the i-th element of the expression, or a SyntaxError if it is missing
+    fn arg(&self, i: usize) -> Result<Box<Expr>, LangError> {
+        match self.items.get(i) {
+            Some(x) => Ok(Box::new(Expr::from_sexp(x)?)),
+            None => Err(LangError::new_syntax_error(
+                format!("\"{}\" expects at least {} argument(s)", self.command, i),
+                self.s.clone(),
+            )),
+        }
+    }
+
+    /// Returns the elements of the expression starting from the i-th
+    fn args_from(&self, i: usize) -> Result<Vec<Expr>, LangError> {
+        self.items.iter().skip(i).map(Expr::from_sexp).collect()
+    }
+
+    /// Returns the source of the expression, whose sub-expressions are the given elements
+    fn source(&self, children: &[&Spanned]) -> Source {
+        Source::new(
+            self.s.clone(),
+            self.e.get_span(),
+            children.iter().map(|x| x.get_span()).collect(),
+        )
+    }
+
+    /// Returns the source of the expression, whose sub-expressions are its elements
+    /// starting from the i-th
+    fn source_from(&self, i: usize) -> Source {
+        self.source(&self.items.iter().skip(i).collect::<Vec<&Spanned>>())
+    }
+
+    fn unary(&self, build: fn(Box<Expr>, Source) -> Expr) -> Result<Expr, LangError> {
+        Ok(build(self.arg(1)?, self.source_from(1)))
+    }
+
+    fn binary(&self, build: fn(Box<Expr>, Box<Expr>, Source) -> Expr) -> Result<Expr, LangError> {
+        Ok(build(self.arg(1)?, self.arg(2)?, self.source_from(1)))
+    }
+
+    fn if_form(&self) -> Result<Expr, LangError> {
+        Ok(Expr::If(
+            self.arg(1)?,
+            self.arg(2)?,
+            self.arg(3)?,
+            self.source_from(1),
+        ))
+    }
+
+    fn let_form(&self) -> Result<Expr, LangError> {
+        let items = self.items;
+        if items.len() >= 5 && items[2].is_atom(":") {
+            Ok(Expr::Let(
+                self.arg(1)?,
+                Some(Type::token_tree(&items[3].get_atom("a type")?)),
+                self.arg(4)?,
+                self.source(&[&items[1], &items[4]]),
+            ))
         } else {
-            current_expr.push(c);
-            just_pushed = false;
+            Ok(Expr::Let(
+                self.arg(1)?,
+                None,
+                self.arg(2)?,
+                self.source_from(1),
+            ))
         }
     }
-    if current_expr.len() > 0 {
-        v.push(current_expr.trim().to_string());
+
+    fn define(&self) -> Result<Expr, LangError> {
+        // (def NAME PARAM1 ... PARAMn [-> TYPE] CORE)
+        let items = self.items;
+        let n = items.len();
+        if n < 3 {
+            return Err(LangError::new_syntax_error(
+                "\"def\" expects a name and a core".to_string(),
+                self.s.clone(),
+            ));
+        }
+        let (params_end, ret) = if n >= 5 && items[n - 3].is_atom("->") {
+            (
+                n - 3,
+                Some(Type::token_tree(&items[n - 2].get_atom("a type")?)),
+            )
+        } else {
+            (n - 1, None)
+        };
+        Ok(Expr::Define(
+            self.arg(1)?,
+            Param::token_tree_list(&items[2..params_end])?,
+            ret,
+            Rc::from(self.arg(n - 1)?),
+            self.source(&[&items[1], &items[n - 1]]),
+        ))
+    }
+
+    fn call(&self) -> Result<Expr, LangError> {
+        Ok(Expr::Call(
+            self.arg(1)?,
+            self.args_from(2)?,
+            self.source_from(1),
+        ))
+    }
+
+    fn enum_form(&self) -> Result<Expr, LangError> {
+        Ok(Expr::Enum(
+            self.arg(1)?,
+            self.items
+                .iter()
+                .skip(2)
+                .map(Variant::token_tree)
+                .collect::<Result<Vec<Variant>, LangError>>()?,
+            self.source(&[&self.items[1]]),
+        ))
+    }
+
+    fn print(&self) -> Result<Expr, LangError> {
+        Ok(Expr::Print(self.args_from(1)?, self.source_from(1)))
+    }
+
+    fn for_form(&self) -> Result<Expr, LangError> {
+        Ok(Expr::For(
+            self.arg(1)?,
+            self.arg(2)?,
+            self.arg(3)?,
+            self.arg(4)?,
+            self.source_from(1),
+        ))
+    }
+
+    fn match_form(&self) -> Result<Expr, LangError> {
+        let items = self.items;
+        Ok(Expr::Match(
+            self.arg(1)?,
+            items
+                .iter()
+                .skip(2)
+                .map(Pattern::token_tree_arm)
+                .collect::<Result<Vec<(Pattern, Expr)>, LangError>>()?,
+            // The bodies of the arms follow the value
+            self.source(
+                &std::iter::once(&items[1])
+                    .chain(
+                        items
+                            .iter()
+                            .skip(2)
+                            .filter_map(|arm| arm.get_items().get(1)),
+                    )
+                    .collect::<Vec<&Spanned>>(),
+            ),
+        ))
+    }
+
+    fn list(&self) -> Result<Expr, LangError> {
+        Ok(Expr::List(self.args_from(1)?))
+    }
+
+    fn enum_construct(&self) -> Result<Expr, LangError> {
+        Ok(Expr::EnumConstruct(
+            self.command.to_string(),
+            self.args_from(1)?,
+            self.source_from(1),
+        ))
+    }
+
+    fn sequence(&self) -> Result<Expr, LangError> {
+        Ok(Expr::Sequence(self.args_from(0)?, self.source_from(0)))
+    }
+}
+
+/// Restores the variables to the values they had before being bound
+fn restore(variables: &mut HashMap<String, Expr>, previous_values: Vec<(String, Option<Expr>)>) {
+    for (name, v) in previous_values.into_iter().rev() {
+        match v {
+            Some(v) => variables.insert(name, v),
+            None => variables.remove(&name),
+        };
+    }
+}
+
+/// Returns true if the string is of the form `ENUM_NAME.ITEM`
+pub(crate) fn is_enum_element(s: &str) -> bool {
+    let is_name = |x: &str| {
+        x.chars().all(|c| c.is_alphanumeric() || c == '_')
+            && x.chars().next().is_some_and(|c| !c.is_ascii_digit())
+    };
+    match s.split_once('.') {
+        Some((enum_name, item)) => is_name(enum_name) && is_name(item),
+        None => false,
     }
-    v
 }
 
 #[cfg(test)]
 mod tests_tokens {
     use crate::tokens::*;
+    use std::io::Cursor;
+
+    /// Executes the program with the given input, and returns its result and its output
+    fn exec_with_input(code: &str, input: &str) -> (Result<Expr, LangError>, String) {
+        let output = SharedOutput::default();
+        let mut context = Context::new(
+            vec![],
+            Box::new(Cursor::new(input.to_string())),
+            Box::new(output.clone()),
+        );
+        let result = Expr::token_tree(code).unwrap().exec_with(&mut context);
+        (result, output.contents())
+    }
 
     #[test]
     fn test_add() {
-        assert_eq!(Expr::token_tree("(+ 1 1)").exec().unwrap(), Expr::Number(2));
+        assert_eq!(
+            Expr::token_tree("(+ 1 1)").unwrap().exec().unwrap(),
+            Expr::Number(2)
+        );
     }
 
     #[test]
     fn test_sub() {
-        assert_eq!(Expr::token_tree("(- 1 1)").exec().unwrap(), Expr::Number(0));
+        assert_eq!(
+            Expr::token_tree("(- 1 1)").unwrap().exec().unwrap(),
+            Expr::Number(0)
+        );
     }
 
     #[test]
     fn test_mul() {
-        assert_eq!(Expr::token_tree("(* 2 1)").exec().unwrap(), Expr::Number(2));
+        assert_eq!(
+            Expr::token_tree("(* 2 1)").unwrap().exec().unwrap(),
+            Expr::Number(2)
+        );
     }
 
     #[test]
     fn test_div() {
-        assert_eq!(Expr::token_tree("(/ 4 2)").exec().unwrap(), Expr::Number(2));
+        assert_eq!(
+            Expr::token_tree("(/ 4 2)").unwrap().exec().unwrap(),
+            Expr::Number(2)
+        );
     }
 
     #[test]
     fn test_equal_false() {
         assert_eq!(
-            Expr::token_tree("(= 4 2)").exec().unwrap(),
+            Expr::token_tree("(= 4 2)").unwrap().exec().unwrap(),
             Expr::Bool(false)
         );
     }
@@ -668,7 +1591,7 @@ mod tests_tokens {
     #[test]
     fn test_equal_true() {
         assert_eq!(
-            Expr::token_tree("(= 4 4)").exec().unwrap(),
+            Expr::token_tree("(= 4 4)").unwrap().exec().unwrap(),
             Expr::Bool(true)
         );
     }
@@ -676,7 +1599,7 @@ mod tests_tokens {
     #[test]
     fn test_not_false() {
         assert_eq!(
-            Expr::token_tree("(! (= 4 4))").exec().unwrap(),
+            Expr::token_tree("(! (= 4 4))").unwrap().exec().unwrap(),
             Expr::Bool(false)
         );
     }
@@ -684,7 +1607,7 @@ mod tests_tokens {
     #[test]
     fn test_not_true() {
         assert_eq!(
-            Expr::token_tree("(! (= 4 2))").exec().unwrap(),
+            Expr::token_tree("(! (= 4 2))").unwrap().exec().unwrap(),
             Expr::Bool(true)
         );
     }
@@ -692,7 +1615,10 @@ mod tests_tokens {
     #[test]
     fn test_if_true() {
         assert_eq!(
-            Expr::token_tree("(if (= 2 2) 2 4)").exec().unwrap(),
+            Expr::token_tree("(if (= 2 2) 2 4)")
+                .unwrap()
+                .exec()
+                .unwrap(),
             Expr::Number(2)
         );
     }
@@ -700,7 +1626,10 @@ mod tests_tokens {
     #[test]
     fn test_if_false() {
         assert_eq!(
-            Expr::token_tree("(if (= 1 2) 2 4)").exec().unwrap(),
+            Expr::token_tree("(if (= 1 2) 2 4)")
+                .unwrap()
+                .exec()
+                .unwrap(),
             Expr::Number(4)
         );
     }
@@ -708,7 +1637,7 @@ mod tests_tokens {
     #[test]
     fn test_let() {
         assert_eq!(
-            Expr::token_tree("((let x 2) x)").exec().unwrap(),
+            Expr::token_tree("((let x 2) x)").unwrap().exec().unwrap(),
             Expr::Number(2)
         );
     }
@@ -716,8 +1645,354 @@ mod tests_tokens {
     #[test]
     fn test_for() {
         assert_eq!(
-            Expr::token_tree("(for i 1 10 (i))").exec().unwrap(),
+            Expr::token_tree("(for i 1 10 (i))")
+                .unwrap()
+                .exec()
+                .unwrap(),
             Expr::Empty
         );
     }
+
+    #[test]
+    fn test_for_bounds() {
+        assert_eq!(
+            Expr::token_tree("((let n 3) (let s 0) (for i 0 (+ n 1) (set s (+ s i))) s)")
+                .unwrap()
+                .exec()
+                .unwrap(),
+            Expr::Number(6)
+        );
+        assert!(Expr::token_tree("(for i 0 \"a\" 1)")
+            .unwrap()
+            .exec()
+            .is_err());
+    }
+
+    #[test]
+    fn test_enum_equal() {
+        assert_eq!(
+            Expr::token_tree("((enum days mon tue) (= days.tue days.tue))")
+                .unwrap()
+                .exec()
+                .unwrap(),
+            Expr::Bool(true)
+        );
+    }
+
+    #[test]
+    fn test_enum_mixing() {
+        assert!(
+            Expr::token_tree("((enum days mon tue) (enum color red) (= days.mon color.red))")
+                .unwrap()
+                .exec()
+                .is_err()
+        );
+        assert!(Expr::token_tree("((enum days mon tue) (= days.mon 0))")
+            .unwrap()
+            .exec()
+            .is_err());
+    }
+
+    #[test]
+    fn test_enum_display() {
+        assert_eq!(
+            Expr::token_tree("((enum days mon tue wed) days.wed)")
+                .unwrap()
+                .exec()
+                .unwrap()
+                .to_string(),
+            "days.wed"
+        );
+    }
+
+    #[test]
+    fn test_variants() {
+        assert_eq!(
+            Expr::token_tree("((enum days mon tue) (variants days))")
+                .unwrap()
+                .exec()
+                .unwrap()
+                .to_string(),
+            "[days.mon, days.tue]"
+        );
+    }
+
+    #[test]
+    fn test_undefined_enum_item() {
+        assert!(Expr::token_tree("((enum days mon tue) days.sun)")
+            .unwrap()
+            .exec()
+            .is_err());
+    }
+
+    #[test]
+    fn test_let_annotation() {
+        assert_eq!(
+            Expr::token_tree("((let x : int 3) x)")
+                .unwrap()
+                .exec()
+                .unwrap(),
+            Expr::Number(3)
+        );
+        assert!(Expr::token_tree("(let x : str 3)").unwrap().exec().is_err());
+        assert!(matches!(
+            Expr::token_tree("(let x : integer 3)").unwrap().exec(),
+            Err(LangError::SyntaxError(_))
+        ));
+        assert_eq!(
+            Expr::token_tree("((enum color red) (let c : color color.red) c)")
+                .unwrap()
+                .exec()
+                .unwrap()
+                .to_string(),
+            "color.red"
+        );
+    }
+
+    #[test]
+    fn test_def_annotation() {
+        assert_eq!(
+            Expr::token_tree("((def add (a : int) (b : int) -> int (+ a b)) (call add 1 2))")
+                .unwrap()
+                .exec()
+                .unwrap(),
+            Expr::Number(3)
+        );
+        assert!(Expr::token_tree(
+            "((def add (a : int) (b : int) -> int (+ a b)) (call add 1 \"a\"))"
+        )
+        .unwrap()
+        .exec()
+        .is_err());
+        assert!(Expr::token_tree("((def f (a : int) -> str a) (call f 1))")
+            .unwrap()
+            .exec()
+            .is_err());
+        assert!(matches!(
+            Expr::token_tree("(def f (a : integer) a)").unwrap().exec(),
+            Err(LangError::SyntaxError(_))
+        ));
+    }
+
+    #[test]
+    fn test_call_arity() {
+        assert!(Expr::token_tree("((def add a b (+ a b)) (call add 1))")
+            .unwrap()
+            .exec()
+            .is_err());
+        assert!(Expr::token_tree("((def add a b (+ a b)) (call add 1 2 3))")
+            .unwrap()
+            .exec()
+            .is_err());
+    }
+
+    #[test]
+    fn test_call_default() {
+        assert_eq!(
+            Expr::token_tree("((def add a (b 10) (+ a b)) (+ (call add 1) (call add 1 2)))")
+                .unwrap()
+                .exec()
+                .unwrap(),
+            Expr::Number(14)
+        );
+        assert_eq!(
+            Expr::token_tree("((def add a (b : int (* a 2)) (+ a b)) (call add 2))")
+                .unwrap()
+                .exec()
+                .unwrap(),
+            Expr::Number(6)
+        );
+    }
+
+    #[test]
+    fn test_param_syntax() {
+        for code in [
+            "(def f (a : int 1 extra) a)",
+            "(def f (a b c) a)",
+            "(def f (a :) a)",
+            "(def f (a 1 : int) a)",
+        ] {
+            assert!(
+                matches!(Expr::token_tree(code), Err(LangError::SyntaxError(_))),
+                "no error on: {}",
+                code
+            );
+        }
+        assert!(Expr::token_tree("(def f (a) (b 1) (c : int) (d : int 1) a)").is_ok());
+    }
+
+    #[test]
+    fn test_call_rest() {
+        assert_eq!(
+            Expr::token_tree("((def f a & r r) (call f 1 2 3))")
+                .unwrap()
+                .exec()
+                .unwrap(),
+            Expr::List(vec![Expr::Number(2), Expr::Number(3)])
+        );
+        assert_eq!(
+            Expr::token_tree("((def f a & r r) (call f 1))")
+                .unwrap()
+                .exec()
+                .unwrap(),
+            Expr::List(vec![])
+        );
+        assert!(Expr::token_tree("((def f a & r r) (call f))")
+            .unwrap()
+            .exec()
+            .is_err());
+    }
+
+    #[test]
+    fn test_undefined_variable() {
+        assert!(Expr::token_tree("(+ x 1)").unwrap().exec().is_err());
+        assert!(Expr::token_tree("((let x 1) (print y))")
+            .unwrap()
+            .exec()
+            .is_err());
+    }
+
+    #[test]
+    fn test_symbol() {
+        assert_eq!(
+            Expr::token_tree("((let x 'red) x)")
+                .unwrap()
+                .exec()
+                .unwrap(),
+            Expr::Symbol("red".to_string())
+        );
+        assert_eq!(
+            Expr::token_tree("(= 'red 'red)").unwrap().exec().unwrap(),
+            Expr::Bool(true)
+        );
+        assert_eq!(
+            Expr::token_tree("(match 'b ('a 1) ('b 2))")
+                .unwrap()
+                .exec()
+                .unwrap(),
+            Expr::Number(2)
+        );
+    }
+
+    #[test]
+    fn test_arithmetic_errors() {
+        assert!(Expr::token_tree("(/ 1 0)").unwrap().exec().is_err());
+        assert!(Expr::token_tree("(% 1 0)").unwrap().exec().is_err());
+        assert!(Expr::token_tree("(+ 2147483647 1)")
+            .unwrap()
+            .exec()
+            .is_err());
+        assert!(Expr::token_tree("(/ -2147483648 -1)")
+            .unwrap()
+            .exec()
+            .is_err());
+    }
+
+    #[test]
+    fn test_error_in_argument() {
+        assert!(Expr::token_tree("((def f x x) (call f (+ 1 \"a\")))")
+            .unwrap()
+            .exec()
+            .is_err());
+    }
+
+    #[test]
+    fn test_syntax_errors() {
+        assert!(Expr::token_tree("(+ 1)").is_err());
+        assert!(Expr::token_tree("(if true 1)").is_err());
+        assert!(Expr::token_tree("(def f)").is_err());
+        assert!(Expr::token_tree("(match 1 2)").is_err());
+        assert!(Expr::token_tree("99999999999").is_err());
+        assert!(Expr::token_tree("(print \"a\"").is_err());
+        // Extra arguments are not silently dropped
+        for code in [
+            "(+ 1 2 3)",
+            "(! true false)",
+            "(let x 0 (set y x))",
+            "(let x : int 0 1)",
+            "(if true 1 2 3)",
+            "(for i 0 3 i i)",
+            "(input 1)",
+        ] {
+            assert!(
+                matches!(Expr::token_tree(code), Err(LangError::SyntaxError(_))),
+                "{}",
+                code
+            );
+        }
+    }
+
+    #[test]
+    fn test_exit() {
+        let result = Expr::token_tree("((let x 1) (exit (+ x 2)) (set x \"a\"))")
+            .unwrap()
+            .exec();
+        assert!(matches!(result, Err(LangError::Exit(ref e)) if e.get_code() == 3));
+        for code in ["(exit 300)", "(exit -1)"] {
+            let result = Expr::token_tree(code).unwrap().exec();
+            assert!(
+                matches!(result, Err(LangError::ArithmeticError(_))),
+                "{}",
+                code
+            );
+        }
+        assert_eq!(
+            Expr::token_tree("(/ 1 0)")
+                .unwrap()
+                .exec()
+                .map_err(|e| e.exit_code()),
+            Err(7)
+        );
+    }
+
+    #[test]
+    fn test_args() {
+        let mut context = Context::stdio(vec!["a".to_string(), "b".to_string()]);
+        assert_eq!(
+            Expr::token_tree("(args)")
+                .unwrap()
+                .exec_with(&mut context)
+                .unwrap(),
+            Expr::List(vec![Expr::Str("a".to_string()), Expr::Str("b".to_string())])
+        );
+    }
+
+    #[test]
+    fn test_env() {
+        std::env::set_var("RUSIL_TEST_ENV", "value");
+        assert_eq!(
+            Expr::token_tree(
+                "(match (env \"RUSIL_TEST_ENV\") ((option.some x) x) (option.none \"\"))"
+            )
+            .unwrap()
+            .exec()
+            .unwrap(),
+            Expr::Str("value".to_string())
+        );
+        assert_eq!(
+            Expr::token_tree("(env \"RUSIL_TEST_UNSET\")")
+                .unwrap()
+                .exec()
+                .unwrap()
+                .to_string(),
+            "option.none"
+        );
+        assert!(Expr::token_tree("(env 1)").unwrap().exec().is_err());
+    }
+
+    #[test]
+    fn test_print() {
+        let (result, output) = exec_with_input("((let x 2) (print \"x = \" x) x)", "");
+        assert_eq!(result.unwrap(), Expr::Number(2));
+        assert_eq!(output, "x = 2");
+    }
+
+    #[test]
+    fn test_input() {
+        let (result, output) = exec_with_input(include_str!("../code/gcd.rsl"), "12\n18\n");
+        assert_eq!(result.unwrap(), Expr::Empty);
+        assert_eq!(output, "Enter a: Enter b: GCD: 6");
+        let (result, _) = exec_with_input("(input)", "abc\n");
+        assert_eq!(result.unwrap(), Expr::Str("abc\n".to_string()));
+    }
 }
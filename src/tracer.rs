@@ -0,0 +1,169 @@
+use crate::ast::{shorten, SourceMap};
+use crate::context::*;
+use crate::enums::*;
+use crate::errors::*;
+use crate::functions::*;
+use crate::interpreter::*;
+use crate::reader::Span;
+use crate::tokens::*;
+use std::collections::HashMap;
+use std::io::Write;
+
+/// Expressions written to the trace
+#[derive(Clone, Debug, PartialEq)]
+pub enum Filter {
+    All,
+    /// Only the calls of functions
+    Calls,
+    /// Only the calls of the function, and the expressions evaluated in its body
+    Function(String),
+}
+
+/// Hook writing a line for each evaluated expression, when its evaluation ends
+struct Tracer {
+    sources: SourceMap,
+    filter: Filter,
+    output: Box<dyn Write>,
+    /// Whether each expression being evaluated is traced, and its position, the innermost last
+    traced: Vec<(bool, Option<Span>)>,
+}
+
+impl Tracer {
+    fn is_traced(&self, e: &Expr, context: &Context) -> bool {
+        let is_call_of =
+            |name: &str| matches!(e, Expr::Call(f, _, _) if **f == Expr::Var(name.to_string()));
+        match &self.filter {
+            Filter::All => true,
+            Filter::Calls => matches!(e, Expr::Call(..)),
+            Filter::Function(name) => is_call_of(name) || context.get_calls().last() == Some(name),
+        }
+    }
+}
+
+impl Hook for Tracer {
+    fn before(
+        &mut self,
+        e: &Expr,
+        _variables: &mut HashMap<String, Expr>,
+        _functions: &mut HashMap<String, Function>,
+        _enums: &mut HashMap<String, Vec<Variant>>,
+        context: &mut Context,
+    ) -> Result<(), LangError> {
+        let traced = self.is_traced(e, context);
+        let span = self.sources.enter(e);
+        self.traced.push((traced, span));
+        Ok(())
+    }
+
+    fn after(&mut self, e: &Expr, result: &Result<Expr, LangError>, _context: &mut Context) {
+        self.sources.leave();
+        let span = match self.traced.pop() {
+            Some((true, span)) => span,
+            _ => return,
+        };
+        // Only the traced expressions count in the depth
+        let depth = self.traced.iter().filter(|(traced, _)| *traced).count();
+        let result = match result {
+            Ok(Expr::Empty) => String::new(),
+            Ok(value) => format!(" => {}", shorten(&value.to_string())),
+            Err(error) => format!(" !! {}", error.get_name()),
+        };
+        let line = match span {
+            Some(span) => self.sources.line(span).to_string(),
+            None => "?".to_string(),
+        };
+        // Like a program writing on a closed pipe, the trace stops silently
+        let _ = writeln!(
+            self.output,
+            "{:>4} {:>3} {}{}{}",
+            line,
+            depth,
+            "  ".repeat(depth),
+            self.sources.snippet(e, span),
+            result
+        );
+    }
+}
+
+/// Executes the program, writing the trace of the expressions kept by the filter to `output`
+///
+/// Each line has the line of the expression in the code, its depth, its source, and its value
+/// after `=>` or the name of its error after `!!`
+/// The inner expressions come before the ones containing them
+pub fn trace(
+    code: &str,
+    context: Context,
+    filter: Filter,
+    output: Box<dyn Write>,
+) -> Result<Expr, LangError> {
+    let tree = Expr::token_tree(code)?;
+    let tracer = Tracer {
+        sources: SourceMap::new(code),
+        filter,
+        output,
+        traced: Vec::new(),
+    };
+    let mut interpreter = Interpreter::with_context(context);
+    interpreter.get_context().add_hook(Box::new(tracer));
+    interpreter.eval(&tree)
+}
+
+#[cfg(test)]
+mod tests_tracer {
+    use crate::tracer::*;
+    use std::io::Cursor;
+
+    const CODE: &str = "(def fact n
+  (if (<= n 1)
+    1
+    (* n (call fact (- n 1)))))
+(call fact 2)";
+
+    /// Traces the program, and returns its result and the trace
+    fn trace_with(code: &str, filter: Filter) -> (Result<Expr, LangError>, String) {
+        let output = SharedOutput::default();
+        let context = Context::new(vec![], Box::new(Cursor::new("")), Box::new(Vec::new()));
+        let result = trace(code, context, filter, Box::new(output.clone()));
+        (result, output.contents())
+    }
+
+    #[test]
+    fn test_trace() {
+        let (result, output) = trace_with("(let x (+ 1 2))\n(* x 2)", Filter::All);
+        assert_eq!(result.unwrap(), Expr::Number(6));
+        assert_eq!(
+            output,
+            "   1   3       1 => 1
+   1   3       2 => 2
+   1   2     (+ 1 2) => 3
+   1   1   (let x (+ 1 2))
+   2   2     x => 3
+   2   2     2 => 2
+   2   1   (* x 2) => 6
+   1   0 (let x (+ 1 2)) (* x 2) => 6
+"
+        );
+        let (result, output) = trace_with("(+ 1 (/ 1 0))", Filter::All);
+        assert!(result.is_err());
+        assert!(output.ends_with(
+            "   1   1   (/ 1 0) !! ArithmeticError\n   1   0 (+ 1 (/ 1 0)) !! ArithmeticError\n"
+        ));
+
+        // The same name is on the line it was read at, in the loop too
+        let (_, output) = trace_with("(for i 0 2\n  (+ i\n    i))", Filter::All);
+        assert!(output.contains("   2   2     i => 0\n   3   2     i => 0\n"));
+        assert!(output.contains("   2   2     i => 1\n   3   2     i => 1\n"));
+    }
+
+    #[test]
+    fn test_filters() {
+        let (_, output) = trace_with(CODE, Filter::Calls);
+        assert_eq!(
+            output,
+            "   4   1   (call fact (- n 1)) => 1\n   5   0 (call fact 2) => 2\n"
+        );
+        let (_, output) = trace_with(CODE, Filter::Function("fact".to_string()));
+        assert!(output.contains("   2   2     (<= n 1) => false\n"));
+        assert!(output.ends_with("   5   0 (call fact 2) => 2\n"));
+    }
+}
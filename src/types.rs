@@ -1,10 +1,49 @@
-use crate::tokens::Expr;
+use crate::enums::Variant;
+use crate::errors::*;
+use std::collections::HashMap;
 
-#[derive(Debug, PartialEq)]
-pub(crate) enum Type {
+#[derive(Debug, PartialEq, Clone)]
+pub enum Type {
     Number,
     Bool,
     Str,
     Expression,
     Var,
+    Symbol,
+    List,
+    Enum(String),
+}
+
+impl Type {
+    /// Returns the type named in an annotation:
+    /// `int`, `bool`, `str`, `list`, `symbol` or the name of an enum
+    pub(crate) fn token_tree(s: &str) -> Self {
+        match s.trim() {
+            "int" => Type::Number,
+            "bool" => Type::Bool,
+            "str" => Type::Str,
+            "list" => Type::List,
+            "symbol" => Type::Symbol,
+            name => Type::Enum(name.to_string()),
+        }
+    }
+
+    /// Returns a SyntaxError if the type is an enum which is not defined,
+    /// like a misspelled `integer`
+    pub(crate) fn check_known(
+        &self,
+        enums: &HashMap<String, Vec<Variant>>,
+        expr_str: &str,
+    ) -> Result<(), LangError> {
+        match self {
+            Type::Enum(name) if !enums.contains_key(name) => Err(LangError::new_syntax_error(
+                format!(
+                    "Unknown type \"{}\": expected int, bool, str, list, symbol or a defined enum",
+                    name
+                ),
+                expr_str.to_string(),
+            )),
+            _ => Ok(()),
+        }
+    }
 }